@@ -4,7 +4,7 @@ use tempfile::TempDir;
 
 use std::path::{PathBuf, Path};
 use std::fs::{File, rename};
-use std::io::{Result, Error, ErrorKind, Write};
+use std::io::{Result, Error, Write};
 
 /// A version of File that should never leave a partially-written
 /// file.  This is only useful for creating files, and will overwrite
@@ -21,28 +21,27 @@ impl AtomicFile {
         let filepath = p.as_ref();
         let p = match filepath.parent() {
             None => {
-                return Err(Error::new(ErrorKind::Other,
-                                      format!("Cannot create a file named {:?}",
-                                              filepath)));
+                return Err(Error::other(format!("Cannot create a file named {:?}",
+                                                filepath)));
             }
-            Some(p) if p.as_os_str().len() == 0 => {
-                &Path::new(".")
+            Some(p) if p.as_os_str().is_empty() => {
+                Path::new(".")
             }
             Some(p) => p,
         };
-        std::fs::create_dir_all(&p).ok();
+        std::fs::create_dir_all(p).ok();
         let dir = TempDir::new_in(p)?;
         let file_path = dir.path().join("temp");
         let file = File::create(file_path)?;
         Ok(AtomicFile {
             path: PathBuf::from(filepath),
-            dir: dir,
-            file: file,
+            dir,
+            file,
         })
     }
 }
 
-impl<'a> Write for &'a AtomicFile {
+impl Write for &AtomicFile {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
         (&self.file).write(buf)
     }
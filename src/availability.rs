@@ -0,0 +1,106 @@
+//! When a thing makes sense at all, such as grilling only in summer or
+//! a recipe that is just for Sundays.  Availability never changes when
+//! a thing is due; things are just skipped while they are unavailable.
+
+use chrono::{Datelike, Local, Month, NaiveDate, Weekday};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Availability {
+    /// The days of the week the thing is available, or every day if
+    /// this is empty.
+    #[serde(default)]
+    pub weekdays: Vec<Weekday>,
+    /// The first and last months (1 to 12) the thing is available.
+    /// The range wraps around the new year, so (12, 2) is winter.
+    #[serde(default)]
+    pub months: Option<(u32, u32)>,
+}
+
+impl Availability {
+    /// Read the availability from a form, e.g. "sat, sun" and "jun-aug".
+    /// Anything we cannot make sense of is ignored.
+    pub fn parse(weekdays: &str, months: &str) -> Self {
+        let weekdays = weekdays
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter_map(|d| d.parse().ok())
+            .collect();
+        let mut ends = months.split('-').filter_map(parse_month);
+        let months = match (ends.next(), ends.next()) {
+            (Some(first), Some(last)) => Some((first, last)),
+            (Some(only), None) => Some((only, only)),
+            _ => None,
+        };
+        Availability { weekdays, months }
+    }
+    pub fn is_always(&self) -> bool {
+        self.weekdays.is_empty() && self.months.is_none()
+    }
+    pub fn on(&self, date: NaiveDate) -> bool {
+        let weekday_ok = self.weekdays.is_empty() || self.weekdays.contains(&date.weekday());
+        let month_ok = match self.months {
+            None => true,
+            Some((first, last)) if first <= last => (first..=last).contains(&date.month()),
+            Some((first, last)) => date.month() >= first || date.month() <= last,
+        };
+        weekday_ok && month_ok
+    }
+    pub fn today(&self) -> bool {
+        self.on(Local::now().date_naive())
+    }
+    pub fn weekdays_text(&self) -> String {
+        self.weekdays
+            .iter()
+            .map(|d| d.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+    pub fn months_text(&self) -> String {
+        match self.months {
+            None => String::new(),
+            Some((first, last)) if first == last => month_name(first),
+            Some((first, last)) => format!("{}-{}", month_name(first), month_name(last)),
+        }
+    }
+}
+
+fn parse_month(m: &str) -> Option<u32> {
+    let m = m.trim();
+    match m.parse::<u32>() {
+        Ok(n) if (1..=12).contains(&n) => Some(n),
+        Ok(_) => None,
+        Err(_) => m.parse::<Month>().ok().map(|m| m.number_from_month()),
+    }
+}
+
+fn month_name(n: u32) -> String {
+    const NAMES: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    NAMES
+        .get(n.wrapping_sub(1) as usize)
+        .map(|m| m.to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weekend_in_winter() {
+        let available = Availability::parse("sat, sun", "dec-feb");
+        assert_eq!(available.weekdays_text(), "Sat, Sun");
+        assert_eq!(available.months_text(), "Dec-Feb");
+        let day = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        assert!(available.on(day(2026, 1, 3)));
+        assert!(available.on(day(2026, 12, 27)));
+        assert!(!available.on(day(2026, 1, 5)));
+        assert!(!available.on(day(2026, 7, 4)));
+    }
+
+    #[test]
+    fn nonsense_means_always() {
+        assert!(Availability::parse("someday", "13").is_always());
+    }
+}
@@ -0,0 +1,165 @@
+//! Export the upcoming things in a list as an iCalendar file, so that
+//! (for instance) a meal rotation shows up in a calendar app.
+
+use crate::{Thing, ThingList};
+use chrono::{Duration, Local, NaiveDate, Utc};
+use serde::Deserialize;
+
+/// Which slots to fill, as given in the query string.
+#[derive(Debug, Default, Deserialize)]
+pub struct Slots {
+    /// How many things to put on the calendar (default 7).
+    count: Option<usize>,
+    /// How many days apart the slots are (default 1).
+    every: Option<i64>,
+    /// The hour (local time) at which each slot starts.  Without it,
+    /// each slot is an all-day event.
+    hour: Option<u32>,
+    /// How many minutes a timed slot lasts (default 60).
+    minutes: Option<i64>,
+}
+
+/// Build the calendar, starting with today and the thing at the top of
+/// the list.  A slot on a day when nothing is available stays empty.
+pub fn ics(list: &ThingList, slots: &Slots) -> String {
+    let today = Local::now().date_naive();
+    let stamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    // The query may ask for anything, so keep the dates well within
+    // what chrono can represent.
+    let every = Duration::days(slots.every.unwrap_or(1).clamp(1, 366));
+    let mut out = String::new();
+    line(&mut out, "BEGIN:VCALENDAR");
+    line(&mut out, "VERSION:2.0");
+    line(&mut out, "PRODID:-//thing-lists//EN");
+    line(&mut out, &format!("X-WR-CALNAME:{}", escape(&list.name)));
+    // Each slot gets the first thing that is available on its day.  A
+    // year of empty slots means nothing left will ever fit.
+    let mut remaining: Vec<&Thing> = list.things.iter().collect();
+    let mut empty = 0;
+    let mut day = today;
+    for _ in 0..slots.count.unwrap_or(7) {
+        let thing = match remaining.iter().position(|x| x.available.on(day)) {
+            Some(i) => remaining.remove(i),
+            None if remaining.is_empty() || empty > 366 => break,
+            None => {
+                empty += 1;
+                day += every;
+                continue;
+            }
+        };
+        empty = 0;
+        line(&mut out, "BEGIN:VEVENT");
+        line(
+            &mut out,
+            &format!(
+                "UID:{}",
+                escape(&format!(
+                    "{}-{}-{}@thing-lists",
+                    day.format("%Y%m%d"),
+                    thing.slug(),
+                    list.code
+                ))
+            ),
+        );
+        line(&mut out, &format!("DTSTAMP:{}", stamp));
+        for l in times(day, slots) {
+            line(&mut out, &l);
+        }
+        line(&mut out, &format!("SUMMARY:{}", escape(&thing.name)));
+        if let Some(ref link) = thing.link {
+            line(&mut out, &format!("URL:{}", clean(link)));
+            line(&mut out, &format!("DESCRIPTION:{}", escape(link)));
+        }
+        line(&mut out, "END:VEVENT");
+        day += every;
+    }
+    line(&mut out, "END:VCALENDAR");
+    out
+}
+
+fn times(day: NaiveDate, slots: &Slots) -> Vec<String> {
+    match slots.hour.and_then(|h| day.and_hms_opt(h, 0, 0)) {
+        Some(start) => {
+            // Floating times, so the calendar app uses its own time zone.
+            let end = start + Duration::minutes(slots.minutes.unwrap_or(60).clamp(1, 1440));
+            vec![
+                format!("DTSTART:{}", start.format("%Y%m%dT%H%M%S")),
+                format!("DTEND:{}", end.format("%Y%m%dT%H%M%S")),
+            ]
+        }
+        None => vec![
+            format!("DTSTART;VALUE=DATE:{}", day.format("%Y%m%d")),
+            format!(
+                "DTEND;VALUE=DATE:{}",
+                (day + Duration::days(1)).format("%Y%m%d")
+            ),
+        ],
+    }
+}
+
+/// Escape text as RFC 5545 requires.
+fn escape(text: &str) -> String {
+    clean(
+        &text
+            .replace('\\', "\\\\")
+            .replace(';', "\\;")
+            .replace(',', "\\,")
+            .replace('\n', "\\n"),
+    )
+}
+
+/// Drop any control characters, which a value that cannot be escaped
+/// (such as a link) could otherwise use to end its line and start
+/// properties of its own.
+fn clean(text: &str) -> String {
+    text.chars().filter(|c| !c.is_control()).collect()
+}
+
+/// Add a content line, folded so no line is longer than 75 octets.
+fn line(out: &mut String, content: &str) {
+    let mut width = 0;
+    for c in content.chars() {
+        if width + c.len_utf8() > 75 {
+            out.push_str("\r\n ");
+            width = 1;
+        }
+        out.push(c);
+        width += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn huge_slots_are_kept_in_range() {
+        let list = ThingList::with_things(&["first", "second", "third"]);
+        let slots = Slots {
+            count: Some(usize::MAX),
+            every: Some(i64::MAX),
+            hour: Some(18),
+            minutes: Some(i64::MAX),
+        };
+        let ics = ics(&list, &slots);
+        assert_eq!(ics.matches("BEGIN:VEVENT").count(), 3);
+    }
+
+    #[test]
+    fn links_cannot_add_properties() {
+        let mut list = ThingList::with_things(&["soup\r\nSTATUS:CANCELLED"]);
+        list.things[0].link =
+            Some("https://example.com/\r\nATTACH:https://evil.example.com".to_string());
+        let ics = ics(&list, &Slots::default());
+        for l in ics.split("\r\n") {
+            assert!(
+                !l.starts_with("ATTACH") && !l.starts_with("STATUS"),
+                "{}",
+                l
+            );
+            assert!(!l.contains(['\r', '\n']), "{:?}", l);
+        }
+        assert!(ics.contains("URL:https://example.com/ATTACH:https://evil.example.com"));
+    }
+}
@@ -0,0 +1,262 @@
+//! Export every list under a code as a single JSON document, and import
+//! such a document, for scripts that would rather not deal with the
+//! YAML files one list at a time.
+//!
+//! Importing replaces each list named in the document, leaving any
+//! other lists under the code alone.  Only the names of the lists and
+//! things are needed, so a script can write a list from scratch.
+
+use crate::store::{self, Store};
+use crate::{history, ThingList};
+use serde::{Deserialize, Serialize};
+
+/// Every list under one code, with all the data of every thing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Collection {
+    pub code: String,
+    pub lists: Vec<ThingList>,
+}
+
+impl Collection {
+    pub fn read(store: &dyn Store, code: &str) -> Self {
+        let mut names = store.lists(code);
+        names.sort();
+        Collection {
+            code: code.to_string(),
+            lists: names
+                .iter()
+                .map(|n| ThingList::read(store, code, n))
+                .collect(),
+        }
+    }
+}
+
+/// What importing did (or would do) to one list.  Things are matched by
+/// name, whether they are scheduled or archived.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ListChange {
+    pub name: String,
+    /// There was no list by this name before.
+    pub new: bool,
+    pub settings: bool,
+    pub added: Vec<String>,
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl ListChange {
+    /// A line saying what happened to the things in the list.
+    pub fn describe(&self) -> String {
+        if self.new {
+            return format!("new, with {} things", self.added.len());
+        }
+        let mut parts = Vec::new();
+        if self.settings {
+            parts.push("settings changed".to_string());
+        }
+        for (what, names) in [
+            ("added", &self.added),
+            ("changed", &self.changed),
+            ("removed", &self.removed),
+        ] {
+            if !names.is_empty() {
+                parts.push(format!("{} {}", what, names.join(", ")));
+            }
+        }
+        if parts.is_empty() {
+            "unchanged".to_string()
+        } else {
+            parts.join("; ")
+        }
+    }
+}
+
+/// Import `collection` into `code`, which need not exist yet.  With
+/// `dry_run` nothing is saved, so this only reports what would change.
+pub fn import(
+    store: &dyn Store,
+    code: &str,
+    collection: Collection,
+    dry_run: bool,
+) -> Result<Vec<ListChange>, String> {
+    let existing = store.lists(code);
+    let mut names: Vec<&str> = Vec::new();
+    // Everything is checked before anything is written, so that a bad
+    // list cannot leave the import half done.
+    for list in collection.lists.iter() {
+        store::check_name(&list.name)?;
+        if list.name.starts_with('.') || list.name.contains('/') {
+            return Err(format!("{:?} cannot be the name of a list", list.name));
+        }
+        if names.contains(&list.name.as_str()) {
+            return Err(format!("there are two lists named {:?}", list.name));
+        }
+        names.push(&list.name);
+        let mut things: Vec<&str> = Vec::new();
+        for thing in list.things.iter().chain(list.archived.iter()) {
+            if thing.name.is_empty() {
+                return Err(format!("a thing in {} has no name", list.name));
+            }
+            if things.contains(&thing.name.as_str()) {
+                return Err(format!(
+                    "there are two things named {:?} in {}",
+                    thing.name, list.name
+                ));
+            }
+            things.push(&thing.name);
+        }
+    }
+    let mut changes = Vec::new();
+    for list in collection.lists {
+        let list = prepare(code, list);
+        let old = ThingList::read(store, code, &list.name);
+        let change = diff(&old, &list, !existing.contains(&list.name));
+        if !dry_run {
+            list.save(store);
+            for name in change.added.iter() {
+                list.record(store, name, history::Action::New);
+            }
+            for name in change.removed.iter() {
+                list.record(store, name, history::Action::Delete);
+            }
+            for name in change.changed.iter() {
+                let th = list
+                    .things
+                    .iter()
+                    .chain(list.archived.iter())
+                    .find(|x| &x.name == name);
+                list.record(
+                    store,
+                    name,
+                    history::Action::Edit {
+                        newname: name.clone(),
+                        link: th.and_then(|x| x.link.clone()),
+                        tags: th.map(|x| x.tags.clone()).unwrap_or_default(),
+                    },
+                );
+            }
+        }
+        changes.push(change);
+    }
+    Ok(changes)
+}
+
+/// Move a list from the document into `code`, so that it and its things
+/// all know where they now live.
+fn prepare(code: &str, mut list: ThingList) -> ThingList {
+    list.code = code.to_string();
+    for thing in list.things.iter_mut().chain(list.archived.iter_mut()) {
+        thing.parent_code = list.code.clone();
+        thing.parent_name = list.name.clone();
+    }
+    list.start_things();
+    list.start_clock();
+    list
+}
+
+/// How `new` differs from `old`, where `is_new` says that there was
+/// no `old` list at all.
+pub fn diff(old: &ThingList, new: &ThingList, is_new: bool) -> ListChange {
+    let json = |x| serde_json::to_value(x).expect("error serializing thing");
+    let old_things: Vec<_> = old.things.iter().chain(old.archived.iter()).collect();
+    let new_things: Vec<_> = new.things.iter().chain(new.archived.iter()).collect();
+    let mut change = ListChange {
+        name: new.name.clone(),
+        new: is_new,
+        settings: !is_new && old.settings != new.settings,
+        ..ListChange::default()
+    };
+    for thing in new_things.iter() {
+        match old_things.iter().find(|x| x.name == thing.name) {
+            None => change.added.push(thing.name.clone()),
+            Some(x) if json(x) != json(thing) => change.changed.push(thing.name.clone()),
+            Some(_) => (),
+        }
+    }
+    for thing in old_things.iter() {
+        if !new_things.iter().any(|x| x.name == thing.name) {
+            change.removed.push(thing.name.clone());
+        }
+    }
+    change
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(code: &str, names: &[&str]) -> ThingList {
+        let mut list = ThingList::with_things(names);
+        list.code = code.to_string();
+        list
+    }
+
+    #[test]
+    fn imported_things_belong_to_their_new_code() {
+        let list = prepare("new", list("old", &["soup"]));
+        assert_eq!(list.code, "new");
+        assert_eq!(list.things[0].parent_code, "new");
+        assert_eq!(list.things[0].parent_name, "list");
+    }
+
+    #[test]
+    fn changes_are_reported_by_name() {
+        let old = list("code", &["soup", "salad", "toast"]);
+        let mut new = list("code", &["soup", "salad", "pie"]);
+        new.things[0].created_at = old.things[0].created_at;
+        new.things[1].count = 2;
+        let change = diff(&old, &new, false);
+        assert_eq!(change.added, vec!["pie"]);
+        assert_eq!(change.changed, vec!["salad"]);
+        assert_eq!(change.removed, vec!["toast"]);
+        assert!(!change.settings);
+    }
+
+    #[test]
+    fn lists_need_only_names() {
+        let store = crate::store::Memory::default();
+        let json = r#"{"code": "elsewhere", "lists": [
+            {"name": "dinner", "things": [{"name": "soup"}, {"name": "salad", "count": 2}]}
+        ]}"#;
+        let collection: Collection = serde_json::from_str(json).unwrap();
+        let changes = import(&store, "code", collection, false).unwrap();
+        assert_eq!(changes[0].added, vec!["soup", "salad"]);
+        let list = ThingList::read(&store, "code", "dinner");
+        assert_eq!(list.things.len(), 2);
+        for thing in list.things.iter() {
+            assert_eq!(thing.parent_code, "code");
+            assert!(!thing.next.is_nan() && !thing.created.is_nan());
+            assert!(thing.created_at.is_some());
+        }
+        assert!(list.now().is_finite());
+    }
+
+    #[test]
+    fn things_may_not_share_a_name() {
+        let store = crate::store::Memory::default();
+        let mut list = list("code", &["soup", "salad"]);
+        let mut soup = list.things[0].clone();
+        soup.count = 3;
+        list.archived.push(soup);
+        let collection = Collection {
+            code: "code".to_string(),
+            lists: vec![list],
+        };
+        assert!(import(&store, "code", collection, true).is_err());
+    }
+
+    #[test]
+    fn nothing_is_written_unless_every_name_is_good() {
+        for (name, thing) in [("lunch", ""), ("lunch\\box", "soup"), ("lunch\0", "soup")] {
+            let store = crate::store::Memory::default();
+            let mut bad = list("code", &[thing]);
+            bad.name = name.to_string();
+            let collection = Collection {
+                code: "code".to_string(),
+                lists: vec![list("code", &["soup"]), bad],
+            };
+            assert!(import(&store, "code", collection, false).is_err());
+            assert!(store.files("code").is_empty());
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//! An append-only log of everything that has happened to a list.
+//!
+//! The history of a list lives in `.history/<list>` in its collection,
+//! with one JSON event per line, so that recording an event only ever
+//! appends to the file.
+
+use crate::scheduler::{Pass, Rating};
+use crate::store::Store;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// What was done to a thing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum Action {
+    New,
+    /// The seed is that of the random number generator used in
+    /// scheduling, so the choice can be replayed exactly.
+    Choose {
+        #[serde(default)]
+        seed: Option<u64>,
+    },
+    Pass {
+        #[serde(default)]
+        seed: Option<u64>,
+        #[serde(default)]
+        pass: Pass,
+    },
+    Edit {
+        newname: String,
+        link: Option<String>,
+        #[serde(default)]
+        tags: Vec<String>,
+    },
+    /// Rate the most recent choice of the thing.
+    Rate {
+        rating: Rating,
+    },
+    Archive,
+    Restore,
+    Delete,
+    /// Reset the order of the whole list.  The thing is empty.
+    Reorder {
+        #[serde(default)]
+        seed: Option<u64>,
+    },
+    /// Undo the last `steps` changes to the list.  The thing is empty.
+    Undo {
+        steps: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    pub when: DateTime<Utc>,
+    /// The name of the thing at the time of the event.
+    pub thing: String,
+    /// The value of `ThingList::now` just after the event.
+    pub now: f64,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+impl Event {
+    pub fn new(thing: &str, now: f64, action: Action) -> Self {
+        Event {
+            when: Utc::now(),
+            thing: thing.to_string(),
+            now,
+            action,
+        }
+    }
+    pub fn describe(&self) -> String {
+        match self.action {
+            Action::New => format!("created {}", self.thing),
+            Action::Choose { .. } => format!("chose {}", self.thing),
+            Action::Pass {
+                pass: Pass::NotNow, ..
+            } => format!("passed on {} for now", self.thing),
+            Action::Pass { .. } => format!("passed on {}, not in the mood", self.thing),
+            Action::Edit { ref newname, .. } if newname != &self.thing => {
+                format!("renamed {} to {}", self.thing, newname)
+            }
+            Action::Edit { .. } => format!("edited {}", self.thing),
+            Action::Rate { rating } => format!("rated {} {}", self.thing, rating.id()),
+            Action::Archive => format!("archived {}", self.thing),
+            Action::Restore => format!("restored {}", self.thing),
+            Action::Delete => format!("deleted {}", self.thing),
+            Action::Reorder { .. } => "reset the order".to_string(),
+            Action::Undo { steps: 1 } => "undid the last change".to_string(),
+            Action::Undo { steps } => format!("undid the last {} changes", steps),
+        }
+    }
+}
+
+fn name(list: &str) -> String {
+    format!(".history/{}", list)
+}
+
+/// Append an event to the history of a list.
+pub fn record(store: &dyn Store, code: &str, list: &str, event: &Event) {
+    let mut line = serde_json::to_string(event).expect("error serializing event");
+    line.push('\n');
+    store.append(code, &name(list), &line);
+}
+
+/// Read the history of a list, oldest first.  Lines we cannot parse
+/// are skipped rather than losing the whole history.
+pub fn read(store: &dyn Store, code: &str, list: &str) -> Vec<Event> {
+    store
+        .read(code, &name(list))
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|l| serde_json::from_str(l).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::Memory;
+
+    #[test]
+    fn events_are_read_back_in_order() {
+        let store = Memory::default();
+        assert!(read(&store, "code", "dinner").is_empty());
+        let events = vec![
+            Event::new("soup", 0.0, Action::New),
+            Event::new("soup", 1.0, Action::Choose { seed: Some(3) }),
+            Event::new(
+                "soup",
+                1.0,
+                Action::Rate {
+                    rating: Rating::Loved,
+                },
+            ),
+            Event::new(
+                "soup",
+                1.0,
+                Action::Pass {
+                    seed: None,
+                    pass: Pass::NotNow,
+                },
+            ),
+            Event::new("", 1.0, Action::Undo { steps: 2 }),
+        ];
+        for event in events.iter() {
+            record(&store, "code", "dinner", event);
+        }
+        record(
+            &store,
+            "code",
+            "lunch",
+            &Event::new("salad", 0.0, Action::New),
+        );
+        assert_eq!(read(&store, "code", "dinner"), events);
+        assert_eq!(read(&store, "code", "lunch").len(), 1);
+        let described: Vec<String> = events.iter().map(Event::describe).collect();
+        assert_eq!(
+            described,
+            vec![
+                "created soup",
+                "chose soup",
+                "rated soup loved",
+                "passed on soup for now",
+                "undid the last 2 changes",
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_lines_are_skipped() {
+        let store = Memory::default();
+        record(
+            &store,
+            "code",
+            "dinner",
+            &Event::new("soup", 0.0, Action::New),
+        );
+        store.append("code", ".history/dinner", "not an event\n");
+        // Events from before passes had a kind, or choices a seed.
+        store.append(
+            "code",
+            ".history/dinner",
+            "{\"when\":\"2020-01-01T00:00:00Z\",\"thing\":\"soup\",\"now\":2.0,\"action\":\"pass\"}\n",
+        );
+        store.append(
+            "code",
+            ".history/dinner",
+            "{\"when\":\"2020-01-01T00:00:00Z\",\"thing\":\"soup\",\"now\":3.0,\"action\":\"choose\"}\n",
+        );
+        let events = read(&store, "code", "dinner");
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            events[1].action,
+            Action::Pass {
+                seed: None,
+                pass: Pass::NotInTheMood
+            }
+        );
+        assert_eq!(events[2].action, Action::Choose { seed: None });
+    }
+}
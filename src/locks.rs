@@ -0,0 +1,86 @@
+//! Changing a list means reading it, changing it and saving it again,
+//! and two requests doing that at once would lose one of the changes.
+//! So each change holds the lock for its list until it has saved, and
+//! changes to a whole collection, such as restoring a backup, hold the
+//! lock for the collection, which keeps out changes to any of its lists.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedMutexGuard, OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
+
+type ListLock = Arc<tokio::sync::Mutex<()>>;
+
+#[derive(Default)]
+pub struct Locks {
+    collections: Mutex<HashMap<String, Arc<RwLock<()>>>>,
+    lists: Mutex<HashMap<(String, String), ListLock>>,
+}
+
+/// Held while one list is being changed.
+pub struct ListGuard {
+    _list: OwnedMutexGuard<()>,
+    _collection: OwnedRwLockReadGuard<()>,
+}
+
+impl Locks {
+    fn collection_lock(&self, code: &str) -> Arc<RwLock<()>> {
+        self.collections
+            .lock()
+            .expect("locks poisoned")
+            .entry(code.to_string())
+            .or_default()
+            .clone()
+    }
+    /// Wait until nobody else is changing the list, or its collection.
+    pub async fn list(&self, code: &str, list: &str) -> ListGuard {
+        let collection = self.collection_lock(code).read_owned().await;
+        let lock = self
+            .lists
+            .lock()
+            .expect("locks poisoned")
+            .entry((code.to_string(), list.to_string()))
+            .or_default()
+            .clone();
+        ListGuard {
+            _list: lock.lock_owned().await,
+            _collection: collection,
+        }
+    }
+    /// Wait until nobody else is changing any list in the collection.
+    pub async fn collection(&self, code: &str) -> OwnedRwLockWriteGuard<()> {
+        self.collection_lock(code).write_owned().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Each task reads a count, waits a moment, and saves one more, as a
+    /// request changing a list does.
+    async fn count(locks: Arc<Locks>, total: Arc<Mutex<usize>>, list: bool) {
+        let _list;
+        let _collection;
+        if list {
+            _list = locks.list("code", "list").await;
+        } else {
+            _collection = locks.collection("code").await;
+        }
+        let n = *total.lock().unwrap();
+        tokio::task::yield_now().await;
+        *total.lock().unwrap() = n + 1;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn changes_do_not_overlap() {
+        let locks = Arc::new(Locks::default());
+        let total = Arc::new(Mutex::new(0));
+        let tasks: Vec<_> = (0..100)
+            .map(|i| tokio::spawn(count(locks.clone(), total.clone(), i % 10 != 0)))
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(*total.lock().unwrap(), 100);
+    }
+}
@@ -1,10 +1,31 @@
+use chrono::{DateTime, Utc};
 use clapme::ClapMe;
 use display_as::{display, with_template, DisplayAs, HTML, URL, UTF8};
+use locks::Locks;
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+use scheduler::{Pass, Rating};
 use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use store::Store;
 use warp::reply::Reply;
 use warp::{path, Filter};
 
 mod atomicfile;
+mod availability;
+mod calendar;
+mod collection;
+mod history;
+mod locks;
+mod notes;
+mod restore;
+mod scheduler;
+mod simulate;
+mod snapshot;
+mod spreadsheet;
+mod sqlite;
+mod store;
+mod undo;
 // mod sheets;
 
 #[derive(Debug, ClapMe, Serialize)]
@@ -19,8 +40,41 @@ struct TlsFlags {
 struct Flags {
     /// Port to serve on, if not port 80.
     port: Option<u16>,
+    /// Seed for the random number generator, to make runs reproducible.
+    seed: Option<u64>,
+    /// Keep the data in this SQLite database rather than in data/.
+    sqlite: Option<String>,
     /// Options for TLS configuration
     _tls: Option<TlsFlags>,
+    _snapshots: snapshot::Flags,
+}
+
+/// Requests that involve randomness may give `?seed=N` to replay
+/// exactly what happened with that seed.
+#[derive(Debug, Deserialize)]
+struct SeedQuery {
+    seed: Option<u64>,
+}
+
+/// Every part of a multipart form, as its name and contents.
+async fn read_multipart(
+    form: warp::multipart::FormData,
+) -> Result<Vec<(String, Vec<u8>)>, warp::Error> {
+    use futures::TryStreamExt;
+    use warp::Buf;
+    form.and_then(|part| async move {
+        let name = part.name().to_string();
+        let data = part
+            .stream()
+            .try_fold(Vec::new(), |mut data, buf| async move {
+                data.extend_from_slice(buf.chunk());
+                Ok(data)
+            })
+            .await?;
+        Ok((name, data))
+    })
+    .try_collect()
+    .await
 }
 
 fn percent_decode(x: &str) -> String {
@@ -32,123 +86,507 @@ fn percent_decode(x: &str) -> String {
 
 #[tokio::main]
 async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(|a| a.as_str()) == Some("simulate") {
+        match simulate::Flags::from_iter(&args[1..]) {
+            Ok(flags) => simulate::run(flags),
+            Err(e) => e.exit(),
+        }
+        return;
+    }
+    if args.get(1).map(|a| a.as_str()) == Some("restore") {
+        match restore::Flags::from_iter(&args[1..]) {
+            Ok(flags) => restore::run(flags),
+            Err(e) => e.exit(),
+        }
+        return;
+    }
+    if args.get(1).map(|a| a.as_str()) == Some("migrate") {
+        match sqlite::Flags::from_iter(&args[1..]) {
+            Ok(flags) => sqlite::run(flags),
+            Err(e) => e.exit(),
+        }
+        return;
+    }
     let flags = Flags::from_args();
+    let rng = Arc::new(Mutex::new(match flags.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    }));
+    // Each request gets its own seed, which we record in the history.
+    let seed = warp::query().map(move |q: SeedQuery| {
+        q.seed
+            .unwrap_or_else(|| rng.lock().expect("rng lock poisoned").gen())
+    });
+    let data = store::open(flags.sqlite.as_deref());
+    let store = {
+        let data = data.clone();
+        warp::any().map(move || data.clone())
+    };
+    let locks = Arc::new(Locks::default());
+    let locks = warp::any().map(move || locks.clone());
     let style_css = path!("style.css").map(|| {
-        const STYLE: &'static str = include_str!("../style.css");
-        Ok(warp::http::Response::builder()
-            .status(200)
-            .header("content-length", STYLE.len())
-            .header("content-type", "text/css")
-            .body(STYLE)
-            .unwrap())
+        const STYLE: &str = include_str!("../style.css");
+        Ok::<_, warp::http::Error>(
+            warp::http::Response::builder()
+                .status(200)
+                .header("content-length", STYLE.len())
+                .header("content-type", "text/css")
+                .body(STYLE)
+                .unwrap(),
+        )
     });
     let edit = path!("edit-thing")
         .and(warp::filters::body::form())
-        .map(|change: EditThing| {
-            println!("creating new thing {:?}", change);
-            change.edit();
-            "okay"
-        });
+        .and(store.clone())
+        .and(locks.clone())
+        .and_then(
+            |change: EditThing, store: Arc<dyn Store>, locks: Arc<Locks>| async move {
+                let _list = locks.list(&change.code, &change.list).await;
+                println!("creating new thing {:?}", change);
+                change.edit(&*store);
+                Ok::<_, warp::Rejection>("okay")
+            },
+        );
     let new = path!("new-thing")
         .and(warp::filters::body::form())
-        .map(|change: NewThing| {
-            println!("creating new thing {:?}", change);
-            change.save();
-            "okay"
-        });
-    let backup = path!("backup" / String).map(|code: String| {
-        let mut output = Vec::new();
-        {
-            let mut ar = tar::Builder::new(&mut output);
-            // Use the directory at one location, but insert it into the archive
-            // with a different name.
-            ar.append_dir_all("data", format!("data/{}", code)).unwrap();
-            ar.finish().unwrap();
-        }
-        Ok(warp::http::Response::builder()
-            .status(200)
-            .header("content-length", output.len())
-            .header("content-type", "application/tar")
-            .header("content-disposition", r#"attachment; filename="data.tar""#)
-            .body(output)
-            .unwrap())
-    });
-    let choose = path!("choose" / String / String / String).map(
-        |code: String, list: String, name: String| {
-            let change = ChooseThing {
-                code: percent_decode(&code),
-                name: percent_decode(&name),
-                list: percent_decode(&list),
-            };
-            println!("choosing thing {:?}", change);
-            display(HTML, &change.choose()).into_response()
-        },
-    );
-    let delay =
-        path!("pass" / String / String / String).map(|code: String, list: String, name: String| {
-            let change = ChooseThing {
-                code: percent_decode(&code),
-                name: percent_decode(&name),
-                list: percent_decode(&list),
-            };
-            println!("delay thing {:?}", change);
-            display(HTML, &change.delay()).into_response()
-        });
+        .and(store.clone())
+        .and(locks.clone())
+        .and_then(
+            |change: NewThing, store: Arc<dyn Store>, locks: Arc<Locks>| async move {
+                let _list = locks.list(&change.code, &change.list).await;
+                println!("creating new thing {:?}", change);
+                change.save(&*store);
+                Ok::<_, warp::Rejection>("okay")
+            },
+        );
+    let settings = path!("settings")
+        .and(warp::filters::body::form())
+        .and(store.clone())
+        .and(locks.clone())
+        .and_then(
+            |change: EditSettings, store: Arc<dyn Store>, locks: Arc<Locks>| async move {
+                let _list = locks.list(&change.code, &change.list).await;
+                println!("changing settings {:?}", change);
+                change.edit(&*store);
+                Ok::<_, warp::Rejection>("okay")
+            },
+        );
+    let archive = path!("archive")
+        .and(warp::filters::body::form())
+        .and(store.clone())
+        .and(locks.clone())
+        .and_then(
+            |change: RemoveThing, store: Arc<dyn Store>, locks: Arc<Locks>| async move {
+                let _list = locks.list(&change.code, &change.list).await;
+                println!("archiving thing {:?}", change);
+                change.archive(&*store);
+                Ok::<_, warp::Rejection>("okay")
+            },
+        );
+    let restore = path!("restore")
+        .and(warp::filters::body::form())
+        .and(store.clone())
+        .and(locks.clone())
+        .and_then(
+            |change: RemoveThing, store: Arc<dyn Store>, locks: Arc<Locks>| async move {
+                let _list = locks.list(&change.code, &change.list).await;
+                println!("restoring thing {:?}", change);
+                change.restore(&*store);
+                Ok::<_, warp::Rejection>("okay")
+            },
+        );
+    let delete = path!("delete")
+        .and(warp::filters::body::form())
+        .and(store.clone())
+        .and(locks.clone())
+        .and_then(
+            |change: RemoveThing, store: Arc<dyn Store>, locks: Arc<Locks>| async move {
+                let _list = locks.list(&change.code, &change.list).await;
+                println!("deleting thing {:?}", change);
+                change.delete(&*store);
+                Ok::<_, warp::Rejection>("okay")
+            },
+        );
+    let undo = path!("undo")
+        .and(warp::filters::body::form())
+        .and(store.clone())
+        .and(locks.clone())
+        .and_then(
+            |change: Undo, store: Arc<dyn Store>, locks: Arc<Locks>| async move {
+                let _list = locks.list(&change.code, &change.list).await;
+                println!("undoing {:?}", change);
+                Ok::<_, warp::Rejection>(display(HTML, &change.undo(&*store)).into_response())
+            },
+        );
+    let backup =
+        path!("backup" / String)
+            .and(store.clone())
+            .map(|code: String, store: Arc<dyn Store>| {
+                let mut output = Vec::new();
+                {
+                    let mut ar = tar::Builder::new(&mut output);
+                    store::archive(&*store, &code, "data", &mut ar).unwrap();
+                    ar.finish().unwrap();
+                }
+                Ok::<_, warp::http::Error>(
+                    warp::http::Response::builder()
+                        .status(200)
+                        .header("content-length", output.len())
+                        .header("content-type", "application/tar")
+                        .header("content-disposition", r#"attachment; filename="data.tar""#)
+                        .body(output)
+                        .unwrap(),
+                )
+            });
+    let choose = path!("choose" / String / String / String)
+        .and(seed.clone())
+        .and(warp::query())
+        .and(store.clone())
+        .and(locks.clone())
+        .and_then(
+            |code: String,
+             list: String,
+             name: String,
+             seed: u64,
+             q: RatingQuery,
+             store: Arc<dyn Store>,
+             locks: Arc<Locks>| async move {
+                let change = ChooseThing {
+                    code: percent_decode(&code),
+                    name: percent_decode(&name),
+                    list: percent_decode(&list),
+                    seed,
+                };
+                let _list = locks.list(&change.code, &change.list).await;
+                println!("choosing thing {:?}", change);
+                Ok::<_, warp::Rejection>(
+                    display(HTML, &change.choose(&*store, q.rating)).into_response(),
+                )
+            },
+        );
+    let rate = path!("rate" / String / String / String / Rating)
+        .and(store.clone())
+        .and(locks.clone())
+        .and_then(
+            |code: String,
+             list: String,
+             name: String,
+             rating: Rating,
+             store: Arc<dyn Store>,
+             locks: Arc<Locks>| async move {
+                let change = RateThing {
+                    code: percent_decode(&code),
+                    name: percent_decode(&name),
+                    list: percent_decode(&list),
+                };
+                let _list = locks.list(&change.code, &change.list).await;
+                println!("rating thing {:?} {:?}", change, rating);
+                Ok::<_, warp::Rejection>(
+                    display(HTML, &change.rate(&*store, rating)).into_response(),
+                )
+            },
+        );
+    let choose_several =
+        path!("choose-several")
+            .and(warp::filters::body::form())
+            .and(seed.clone())
+            .and(store.clone())
+            .and(locks.clone())
+            .and_then(
+                |form: Vec<(String, String)>,
+                 seed: u64,
+                 store: Arc<dyn Store>,
+                 locks: Arc<Locks>| async move {
+                    let change = ChooseSeveral::from_form(form, seed);
+                    let _list = locks.list(&change.code, &change.list).await;
+                    println!("choosing several things {:?}", change);
+                    change.choose(&*store);
+                    Ok::<_, warp::Rejection>("okay")
+                },
+            );
+    let delay = path!("pass" / String / String / String)
+        .and(seed.clone())
+        .and(warp::query())
+        .and(store.clone())
+        .and(locks.clone())
+        .and_then(
+            |code: String,
+             list: String,
+             name: String,
+             seed: u64,
+             q: PassQuery,
+             store: Arc<dyn Store>,
+             locks: Arc<Locks>| async move {
+                let change = ChooseThing {
+                    code: percent_decode(&code),
+                    name: percent_decode(&name),
+                    list: percent_decode(&list),
+                    seed,
+                };
+                let _list = locks.list(&change.code, &change.list).await;
+                let pass = q.pass.unwrap_or_default();
+                println!("delay thing {:?} {:?}", change, pass);
+                Ok::<_, warp::Rejection>(
+                    display(HTML, &change.delay(&*store, pass)).into_response(),
+                )
+            },
+        );
     let index = (warp::path::end().or(path!("index.html"))).map(|_| {
         println!("I am doing index.");
         display(HTML, &Index {}).into_response()
     });
-    let search = path!("search" / String / String / String).map(
-        |code: String, listname: String, pattern: String| {
+    let search = path!("search" / String / String / String)
+        .and(store.clone())
+        .map(
+            |code: String, listname: String, pattern: String, store: Arc<dyn Store>| {
+                let listname = percent_encoding::percent_decode(listname.as_bytes())
+                    .decode_utf8()
+                    .unwrap();
+                let code = percent_encoding::percent_decode(code.as_bytes())
+                    .decode_utf8()
+                    .unwrap();
+                let pattern = if pattern != "qqq" {
+                    percent_encoding::percent_decode(pattern.as_bytes())
+                        .decode_utf8()
+                        .unwrap()
+                } else {
+                    "".into()
+                };
+                let x = ThingsOnly(ThingList::read(&*store, &code, &listname).filter(&pattern));
+                display(HTML, &x).into_response()
+            },
+        );
+    // Sorting here only changes what we show, so it is safe for a GET.
+    let sort = path!("sort" / String / String)
+        .and(warp::query())
+        .and(seed.clone())
+        .and(store.clone())
+        .map(
+            |code: String, listname: String, q: SortQuery, seed: u64, store: Arc<dyn Store>| {
+                let list =
+                    ThingList::read(&*store, &percent_decode(&code), &percent_decode(&listname));
+                let x = ThingsOnly(match q.by {
+                    Some(by) => list.sorted_by(by),
+                    None => list.sorted(&mut StdRng::seed_from_u64(seed)),
+                });
+                display(HTML, &x).into_response()
+            },
+        );
+    let reorder = path!("reorder")
+        .and(warp::post())
+        .and(warp::filters::body::form())
+        .and(seed)
+        .and(store.clone())
+        .and(locks.clone())
+        .and_then(
+            |change: Reorder, seed: u64, store: Arc<dyn Store>, locks: Arc<Locks>| async move {
+                let _list = locks.list(&change.code, &change.list).await;
+                println!("reordering {:?}", change);
+                change.reorder(&*store, seed);
+                Ok::<_, warp::Rejection>("okay")
+            },
+        );
+    let archived = path!("archived" / String / String).and(store.clone()).map(
+        |code: String, listname: String, store: Arc<dyn Store>| {
+            let x = Archived(ThingList::read(
+                &*store,
+                &percent_decode(&code),
+                &percent_decode(&listname),
+            ));
+            display(HTML, &x).into_response()
+        },
+    );
+    let calendar = path!("calendar" / String / String)
+        .and(warp::query())
+        .and(store.clone())
+        .map(
+            |code: String, listname: String, slots: calendar::Slots, store: Arc<dyn Store>| {
+                let listname = percent_decode(&listname);
+                let listname = listname.strip_suffix(".ics").unwrap_or(&listname);
+                let list = ThingList::read(&*store, &percent_decode(&code), listname);
+                let output = calendar::ics(&list, &slots);
+                Ok::<_, warp::http::Error>(
+                    warp::http::Response::builder()
+                        .status(200)
+                        .header("content-length", output.len())
+                        .header("content-type", "text/calendar; charset=utf-8")
+                        .body(output)
+                        .unwrap(),
+                )
+            },
+        );
+    let export = path!("export" / String / String).and(store.clone()).map(
+        |code: String, listname: String, store: Arc<dyn Store>| {
+            let listname = percent_decode(&listname);
+            let listname = listname.strip_suffix(".csv").unwrap_or(&listname);
+            let list = ThingList::read(&*store, &percent_decode(&code), listname);
+            let output = spreadsheet::export(&list);
+            Ok::<_, warp::http::Error>(
+                warp::http::Response::builder()
+                    .status(200)
+                    .header("content-length", output.len())
+                    .header("content-type", "text/csv; charset=utf-8")
+                    .header(
+                        "content-disposition",
+                        format!(
+                            r#"attachment; filename="{}.csv""#,
+                            listname.replace('"', "")
+                        ),
+                    )
+                    .body(output)
+                    .unwrap(),
+            )
+        },
+    );
+    let restore_backup = path!("restore" / String)
+        .and(warp::post())
+        .and(warp::multipart::form().max_length(1 << 26))
+        .and(store.clone())
+        .and(locks.clone())
+        .and_then(
+            |code: String,
+             form: warp::multipart::FormData,
+             store: Arc<dyn Store>,
+             locks: Arc<Locks>| async move {
+                let code = percent_decode(&code);
+                let parts = read_multipart(form).await.map_err(|_| warp::reject())?;
+                let has = |name: &str| parts.iter().any(|(n, _)| n == name);
+                let (merge, dry_run) = (has("merge"), has("dry_run"));
+                let backup = parts
+                    .iter()
+                    .find(|(n, _)| n == "backup")
+                    .map(|(_, data)| restore::Backup::read(data))
+                    .unwrap_or_else(|| Err("there is no archive".to_string()));
+                println!("restoring {} merge {} dry run {}", code, merge, dry_run);
+                let _collection = locks.collection(&code).await;
+                Ok::<_, warp::Rejection>(restore::reply(&*store, &code, backup, merge, dry_run))
+            },
+        );
+    let snapshots = path!("snapshots" / String).map(|code: String| {
+        let x = Snapshots {
+            code: percent_decode(&code),
+            snapshots: snapshot::list(),
+        };
+        display(HTML, &x).into_response()
+    });
+    let restore_snapshot = path!("restore-snapshot")
+        .and(warp::post())
+        .and(warp::filters::body::form())
+        .and(store.clone())
+        .and(locks.clone())
+        .and_then(
+            |change: RestoreSnapshot, store: Arc<dyn Store>, locks: Arc<Locks>| async move {
+                println!("restoring snapshot {:?}", change);
+                let _collection = locks.collection(&change.code).await;
+                let backup = match snapshot::Snapshot::read(&change.snapshot) {
+                    Some(data) => restore::Backup::from_snapshot(&data, &change.code),
+                    None => Err(format!("there is no snapshot {}", change.snapshot)),
+                };
+                Ok::<_, warp::Rejection>(restore::reply(
+                    &*store,
+                    &change.code,
+                    backup,
+                    change.merge.is_some(),
+                    change.dry_run.is_some(),
+                ))
+            },
+        );
+    let export_collection =
+        path!("export" / String)
+            .and(store.clone())
+            .map(|code: String, store: Arc<dyn Store>| {
+                let code = percent_decode(&code);
+                let code = code.strip_suffix(".json").unwrap_or(&code);
+                warp::reply::json(&collection::Collection::read(&*store, code))
+            });
+    let import_collection = path!("import" / String)
+        .and(warp::post())
+        .and(warp::query())
+        .and(warp::body::json())
+        .and(store.clone())
+        .and(locks.clone())
+        .and_then(
+            |code: String,
+             q: ImportQuery,
+             c: collection::Collection,
+             store: Arc<dyn Store>,
+             locks: Arc<Locks>| async move {
+                let code = percent_decode(&code);
+                println!("importing {} lists into {} {:?}", c.lists.len(), code, q);
+                let _collection = locks.collection(&code).await;
+                Ok::<_, warp::Rejection>(match collection::import(&*store, &code, c, q.dry_run) {
+                    Ok(changes) => warp::reply::json(&changes).into_response(),
+                    Err(e) => warp::reply::with_status(e, warp::http::StatusCode::BAD_REQUEST)
+                        .into_response(),
+                })
+            },
+        );
+    let import = path!("import")
+        .and(warp::post())
+        .and(warp::multipart::form().max_length(1 << 22))
+        .and(store.clone())
+        .and(locks.clone())
+        .and_then(
+            |form: warp::multipart::FormData, store: Arc<dyn Store>, locks: Arc<Locks>| async move {
+                let change = spreadsheet::Import::from_form(form)
+                    .await
+                    .map_err(|_| warp::reject())?;
+                println!("importing into {} {}", change.code, change.list);
+                let _list = locks.list(&change.code, &change.list).await;
+                Ok::<_, warp::Rejection>(match change.import(&*store) {
+                    Ok(n) => format!("imported {} things", n).into_response(),
+                    Err(e) => {
+                        println!("error importing: {}", e);
+                        warp::reply::with_status(e, warp::http::StatusCode::BAD_REQUEST)
+                            .into_response()
+                    }
+                })
+            },
+        );
+    let stats = path!("stats" / String / String).and(store.clone()).map(
+        |code: String, listname: String, store: Arc<dyn Store>| {
+            let x = Stats(ThingList::read(
+                &*store,
+                &percent_decode(&code),
+                &percent_decode(&listname),
+            ));
+            display(HTML, &x).into_response()
+        },
+    );
+    let history = path!("history" / String / String).and(store.clone()).map(
+        |code: String, listname: String, store: Arc<dyn Store>| {
+            let x = History::read(&*store, &percent_decode(&code), &percent_decode(&listname));
+            display(HTML, &x).into_response()
+        },
+    );
+    let list = path!(String / String).and(store.clone()).map(
+        |code: String, listname: String, store: Arc<dyn Store>| {
             let listname = percent_encoding::percent_decode(listname.as_bytes())
                 .decode_utf8()
                 .unwrap();
             let code = percent_encoding::percent_decode(code.as_bytes())
                 .decode_utf8()
                 .unwrap();
-            let pattern = if pattern != "qqq" {
-                percent_encoding::percent_decode(pattern.as_bytes())
-                    .decode_utf8()
-                    .unwrap()
-            } else {
-                "".into()
-            };
-            let x = ThingsOnly(ThingList::read(&code, &listname).filter(&pattern));
+            let x = ListPage::read(&*store, &code, &listname);
             display(HTML, &x).into_response()
         },
     );
-    let sort = path!("sort" / String / String).map(|code: String, listname: String| {
-        println!("I am sorting the list.");
-        let listname = percent_encoding::percent_decode(listname.as_bytes())
-            .decode_utf8()
-            .unwrap();
-        let code = percent_encoding::percent_decode(code.as_bytes())
-            .decode_utf8()
-            .unwrap();
-        let x = ThingsOnly(ThingList::read(&code, &listname).sorted());
-        println!("I am done sorting the list.");
-        display(HTML, &x).into_response()
-    });
-    let list = path!(String / String).map(|code: String, listname: String| {
-        let listname = percent_encoding::percent_decode(listname.as_bytes())
-            .decode_utf8()
-            .unwrap();
-        let code = percent_encoding::percent_decode(code.as_bytes())
-            .decode_utf8()
-            .unwrap();
-        let x = ThingList::read(&code, &listname);
-        display(HTML, &x).into_response()
-    });
-    let list_of_lists = path!(String).map(|code: String| {
-        println!("list of lists: {}", code);
-        let code = percent_encoding::percent_decode(code.as_bytes())
-            .decode_utf8()
-            .unwrap();
-        let x = ThingList::read(&code, "fixme");
-        display(HTML, &x).into_response()
-    });
+    let list_of_lists =
+        path!(String)
+            .and(store.clone())
+            .map(|code: String, store: Arc<dyn Store>| {
+                println!("list of lists: {}", code);
+                let code = percent_encoding::percent_decode(code.as_bytes())
+                    .decode_utf8()
+                    .unwrap();
+                let x = Dashboard::read(&*store, &code);
+                display(HTML, &x).into_response()
+            });
 
-    // let sheets_filter = sheets::sheets();
+    // let sheets_filter = sheets::sheets(data.clone());
+
+    tokio::spawn(snapshot::run(flags._snapshots.clone(), data));
 
     if let Some(tls) = flags._tls {
         lets_encrypt_warp::lets_encrypt(
@@ -156,11 +594,30 @@ async fn main() {
                 // .or(sheets_filter)
                 .or(backup)
                 .or(edit)
+                .or(settings)
+                .or(undo)
+                .or(archive)
+                .or(restore)
+                .or(delete)
                 .or(new)
                 .or(choose)
+                .or(choose_several)
+                .or(rate)
                 .or(delay)
                 .or(sort)
+                .or(reorder)
                 .or(search)
+                .or(history)
+                .or(stats)
+                .or(calendar)
+                .or(export)
+                .or(import)
+                .or(export_collection)
+                .or(import_collection)
+                .or(restore_backup)
+                .or(snapshots)
+                .or(restore_snapshot)
+                .or(archived)
                 .or(list)
                 .or(list_of_lists)
                 .or(index),
@@ -175,11 +632,30 @@ async fn main() {
                 // .or(sheets_filter)
                 .or(backup)
                 .or(edit)
+                .or(settings)
+                .or(undo)
+                .or(archive)
+                .or(restore)
+                .or(delete)
                 .or(new)
                 .or(choose)
+                .or(choose_several)
+                .or(rate)
                 .or(delay)
                 .or(sort)
+                .or(reorder)
                 .or(search)
+                .or(history)
+                .or(stats)
+                .or(calendar)
+                .or(export)
+                .or(import)
+                .or(export_collection)
+                .or(import_collection)
+                .or(restore_backup)
+                .or(snapshots)
+                .or(restore_snapshot)
+                .or(archived)
                 .or(list)
                 .or(list_of_lists)
                 .or(index),
@@ -193,6 +669,70 @@ struct Index {}
 #[with_template("[%" "%]" "index.html")]
 impl DisplayAs<HTML> for Index {}
 
+/// Every list in a collection, with what to do next from each.
+struct Dashboard {
+    code: String,
+    lists: Vec<ThingList>,
+}
+#[with_template("[%" "%]" "dashboard.html")]
+impl DisplayAs<HTML> for Dashboard {}
+
+impl Dashboard {
+    fn read(store: &dyn Store, code: &str) -> Self {
+        let mut names = store.lists(code);
+        names.sort();
+        Dashboard {
+            code: code.to_string(),
+            lists: names
+                .iter()
+                .map(|n| ThingList::read(store, code, n))
+                .collect(),
+        }
+    }
+    /// The suggestions we show for each list.
+    fn top(list: &ThingList) -> Vec<&Thing> {
+        list.available_today().into_iter().take(3).collect()
+    }
+}
+
+/// The snapshots that a collection could be restored from.
+struct Snapshots {
+    code: String,
+    snapshots: Vec<snapshot::Snapshot>,
+}
+#[with_template("[%" "%]" "snapshots.html")]
+impl DisplayAs<HTML> for Snapshots {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct RestoreSnapshot {
+    code: String,
+    snapshot: String,
+    #[serde(default)]
+    merge: Option<String>,
+    #[serde(default)]
+    dry_run: Option<String>,
+}
+
+struct History {
+    code: String,
+    name: String,
+    events: Vec<history::Event>,
+}
+#[with_template("[%" "%]" "history.html")]
+impl DisplayAs<HTML> for History {}
+
+impl History {
+    fn read(store: &dyn Store, code: &str, name: &str) -> Self {
+        let mut events = history::read(store, code, name);
+        events.reverse();
+        History {
+            code: code.to_string(),
+            name: name.to_string(),
+            events,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 struct NewThing {
     code: String,
@@ -201,22 +741,13 @@ struct NewThing {
 }
 
 impl NewThing {
-    fn save(&self) {
-        let mut list = ThingList::read(&self.code, &self.list);
-        let now = list.now();
-        let newthing = Thing {
-            name: self.name.clone(),
-            link: None,
-            next: now,
-            first_chosen: now,
-            chosen: now,
-            created: now,
-            count: 0,
-            parent_code: self.code.clone(),
-            parent_name: self.list.clone(),
-        };
-        list.things.push(newthing);
-        list.save();
+    fn save(&self, store: &dyn Store) {
+        let mut list = ThingList::read(store, &self.code, &self.list);
+        if !list.add(&self.name) {
+            return;
+        }
+        list.save(store);
+        list.record(store, &self.name, history::Action::New);
     }
 }
 
@@ -225,23 +756,129 @@ struct ChooseThing {
     code: String,
     name: String,
     list: String,
+    seed: u64,
+}
+
+/// Rating the most recent choice of a thing, which involves nothing
+/// random.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct RateThing {
+    code: String,
+    name: String,
+    list: String,
+}
+
+impl RateThing {
+    fn rate(&self, store: &dyn Store, rating: Rating) -> ThingsOnly {
+        let mut list = ThingList::read(store, &self.code, &self.list);
+        list.rate(&self.name, rating);
+        list.save(store);
+        list.record(store, &self.name, history::Action::Rate { rating });
+        ThingsOnly(list)
+    }
+}
+
+/// A choice may be rated at the same time it is made.
+#[derive(Debug, Deserialize)]
+struct RatingQuery {
+    rating: Option<Rating>,
+}
+
+/// Importing a collection may only report what it would change.
+#[derive(Debug, Deserialize)]
+struct ImportQuery {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// A pass may say why the thing was passed over.
+#[derive(Debug, Deserialize)]
+struct PassQuery {
+    pass: Option<Pass>,
 }
 
 impl ChooseThing {
-    fn choose(&self) -> ThingsOnly {
-        let mut list = ThingList::read(&self.code, &self.list);
-        list.choose(&self.name);
-        list.save();
+    fn choose(&self, store: &dyn Store, rating: Option<Rating>) -> ThingsOnly {
+        let mut list = ThingList::read(store, &self.code, &self.list);
+        list.choose(&self.name, &mut StdRng::seed_from_u64(self.seed));
+        if let Some(rating) = rating {
+            list.rate(&self.name, rating);
+        }
+        list.save(store);
+        list.record(
+            store,
+            &self.name,
+            history::Action::Choose {
+                seed: Some(self.seed),
+            },
+        );
+        if let Some(rating) = rating {
+            list.record(store, &self.name, history::Action::Rate { rating });
+        }
         ThingsOnly(list)
     }
-    fn delay(&self) -> ThingsOnly {
-        let mut list = ThingList::read(&self.code, &self.list);
-        list.delay(&self.name);
-        list.save();
+    fn delay(&self, store: &dyn Store, pass: Pass) -> ThingsOnly {
+        let mut list = ThingList::read(store, &self.code, &self.list);
+        list.delay(&self.name, pass, &mut StdRng::seed_from_u64(self.seed));
+        list.save(store);
+        list.record(
+            store,
+            &self.name,
+            history::Action::Pass {
+                seed: Some(self.seed),
+                pass,
+            },
+        );
         ThingsOnly(list)
     }
 }
 
+/// Several things that were all done at once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ChooseSeveral {
+    code: String,
+    list: String,
+    names: Vec<String>,
+    seed: u64,
+}
+
+impl ChooseSeveral {
+    /// The form has a `name` field for each thing chosen, which is more
+    /// than serde can put in a struct for us.
+    fn from_form(form: Vec<(String, String)>, seed: u64) -> Self {
+        let mut change = ChooseSeveral {
+            code: String::new(),
+            list: String::new(),
+            names: Vec::new(),
+            seed,
+        };
+        for (k, v) in form {
+            match k.as_str() {
+                "code" => change.code = v,
+                "list" => change.list = v,
+                "name" => change.names.push(v),
+                _ => (),
+            }
+        }
+        change
+    }
+    fn choose(&self, store: &dyn Store) {
+        let mut list = ThingList::read(store, &self.code, &self.list);
+        let names: Vec<&str> = self.names.iter().map(|n| n.as_str()).collect();
+        let chosen = list.choose_all(&names, &mut StdRng::seed_from_u64(self.seed));
+        list.save(store);
+        for name in chosen.iter() {
+            list.record(
+                store,
+                name,
+                history::Action::Choose {
+                    seed: Some(self.seed),
+                },
+            );
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 struct EditThing {
     code: String,
@@ -249,31 +886,235 @@ struct EditThing {
     list: String,
     link: String,
     newname: String,
+    /// Comma-separated tags.  When absent, the tags are left alone.
+    #[serde(default)]
+    tags: Option<String>,
+    /// Overrides the cooldown of the list.  When absent, the cooldown
+    /// is left alone.
+    #[serde(default)]
+    cooldown: Option<String>,
+    #[serde(default)]
+    cooldown_unit: String,
+    /// The days of the week the thing is available, e.g. "sat, sun".
+    /// When absent, the availability is left alone.
+    #[serde(default)]
+    available_days: Option<String>,
+    /// The months the thing is available, e.g. "jun-aug".
+    #[serde(default)]
+    available_months: Option<String>,
+    /// Markdown notes.  When absent, the notes are left alone.
+    #[serde(default)]
+    notes: Option<String>,
 }
 
 impl EditThing {
-    fn edit(&self) {
-        let mut list = ThingList::read(&self.code, &self.list);
-        let th = list.edit(&self.name);
-        th.name = self.newname.clone();
-        if self.link.len() > 0 {
+    fn edit(&self, store: &dyn Store) {
+        let mut list = ThingList::read(store, &self.code, &self.list);
+        let th = match list.edit(&self.name, &self.newname) {
+            Some(th) => th,
+            None => return,
+        };
+        if !self.link.is_empty() {
             th.link = Some(self.link.clone());
         }
-        list.save();
+        if let Some(ref tags) = self.tags {
+            th.tags = parse_tags(tags);
+        }
+        if let Some(ref cooldown) = self.cooldown {
+            th.cooldown = scheduler::Cooldown::parse(cooldown, &self.cooldown_unit);
+        }
+        if self.available_days.is_some() || self.available_months.is_some() {
+            th.available = availability::Availability::parse(
+                self.available_days.as_deref().unwrap_or(""),
+                self.available_months.as_deref().unwrap_or(""),
+            );
+        }
+        if let Some(ref notes) = self.notes {
+            th.notes = notes.trim().to_string();
+        }
+        let link = th.link.clone();
+        let tags = th.tags.clone();
+        list.save(store);
+        list.record(
+            store,
+            &self.name,
+            history::Action::Edit {
+                newname: self.newname.clone(),
+                link,
+                tags,
+            },
+        );
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct RemoveThing {
+    code: String,
+    name: String,
+    list: String,
+}
+
+impl RemoveThing {
+    fn archive(&self, store: &dyn Store) {
+        let mut list = ThingList::read(store, &self.code, &self.list);
+        list.archive(&self.name);
+        list.save(store);
+        list.record(store, &self.name, history::Action::Archive);
+    }
+    fn restore(&self, store: &dyn Store) {
+        let mut list = ThingList::read(store, &self.code, &self.list);
+        list.restore(&self.name);
+        list.save(store);
+        list.record(store, &self.name, history::Action::Restore);
+    }
+    fn delete(&self, store: &dyn Store) {
+        let mut list = ThingList::read(store, &self.code, &self.list);
+        list.delete(&self.name);
+        list.save(store);
+        list.record(store, &self.name, history::Action::Delete);
+    }
+}
+
+/// Ways to look at a list without changing it.  Without one of these,
+/// `/sort` shows the order that `/reorder` would give.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum SortBy {
+    /// The order of the schedule.
+    Next,
+    /// Most chosen first.
+    Count,
+    /// Most recently chosen first.
+    Chosen,
+    Name,
+}
+
+#[derive(Debug, Deserialize)]
+struct SortQuery {
+    by: Option<SortBy>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Reorder {
+    code: String,
+    list: String,
+}
+
+impl Reorder {
+    fn reorder(&self, store: &dyn Store, seed: u64) {
+        let list =
+            ThingList::read(store, &self.code, &self.list).sorted(&mut StdRng::seed_from_u64(seed));
+        list.save(store);
+        list.record(store, "", history::Action::Reorder { seed: Some(seed) });
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Undo {
+    code: String,
+    list: String,
+    #[serde(default)]
+    steps: Option<usize>,
+}
+
+impl Undo {
+    fn undo(&self, store: &dyn Store) -> ThingsOnly {
+        ThingsOnly(ThingList::undo(
+            store,
+            &self.code,
+            &self.list,
+            self.steps.unwrap_or(1),
+        ))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct EditSettings {
+    code: String,
+    list: String,
+    /// Present (as "on") when the checkbox is checked.
+    real_time: Option<String>,
+    #[serde(default)]
+    scheduler: scheduler::Kind,
+    #[serde(default)]
+    cooldown: String,
+    #[serde(default)]
+    cooldown_unit: String,
+}
+
+impl EditSettings {
+    fn edit(&self, store: &dyn Store) {
+        let mut list = ThingList::read(store, &self.code, &self.list);
+        list.settings.real_time = self.real_time.is_some();
+        list.settings.scheduler = self.scheduler;
+        list.settings.cooldown = scheduler::Cooldown::parse(&self.cooldown, &self.cooldown_unit);
+        list.save(store);
+    }
+}
+
+fn unknown_time() -> f64 {
+    f64::NAN
+}
+
+/// How many of the most recent ratings of a thing the scheduler uses.
+const RECENT_RATINGS: usize = 5;
+
+/// How many of its passes a thing keeps, beyond which we only count
+/// them.  It is also as many passes in a row as can slow a thing down.
+const RECENT_PASSES: usize = 5;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct Thing {
     name: String,
     #[serde(default)]
     link: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Markdown notes, such as ingredients or where the book is.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    notes: String,
+    /// Overrides the cooldown of the list.
+    #[serde(default)]
+    cooldown: Option<scheduler::Cooldown>,
+    /// A rating for each choice that was rated, oldest first.
+    #[serde(default)]
+    ratings: Vec<scheduler::Rated>,
+    /// The most recent passes.
+    #[serde(default)]
+    passes: Vec<scheduler::Passed>,
+    /// How many of each pass, in the order of `Pass::ALL`, came before
+    /// those in `passes`.
+    #[serde(default)]
+    older_passes: [usize; 2],
+    /// How much being passed over slows the thing down, as the log of
+    /// `mood_factor`.  Each pass adds to it and each choice halves it.
+    #[serde(default)]
+    mood: f64,
+    #[serde(default)]
+    available: availability::Availability,
+    /// The times in picks may be left out when a thing is imported,
+    /// and are then filled in by `ThingList::start_things`.
+    #[serde(default = "unknown_time")]
     created: f64,
+    #[serde(default)]
+    created_at: Option<DateTime<Utc>>,
+    #[serde(default = "unknown_time")]
     first_chosen: f64,
+    #[serde(default)]
+    first_chosen_at: Option<DateTime<Utc>>,
+    #[serde(default = "unknown_time")]
     chosen: f64,
+    #[serde(default)]
+    chosen_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    passed_at: Option<DateTime<Utc>>,
+    #[serde(default = "unknown_time")]
     next: f64,
+    #[serde(default)]
     count: u64,
+    #[serde(default)]
     parent_code: String,
+    #[serde(default)]
     parent_name: String,
 }
 #[with_template("[%" "%]" "thing.html")]
@@ -290,123 +1131,525 @@ impl Thing {
     fn priority(&self) -> f64 {
         self.next
     }
-    fn mean_interval(&self) -> f64 {
-        if self.count < 2 {
+    fn notes_html(&self) -> String {
+        notes::render(&self.notes)
+    }
+    /// The geometric mean of the factors from the most recent ratings,
+    /// so that how a thing is enjoyed can change over time.
+    fn rating_factor(&self) -> f64 {
+        let recent: Vec<f64> = self
+            .ratings
+            .iter()
+            .rev()
+            .take(RECENT_RATINGS)
+            .map(|r| r.rating.factor())
+            .collect();
+        if recent.is_empty() {
             1.0
         } else {
-            (self.chosen - self.first_chosen) / (self.count as f64 - 1.0)
-        }
-    }
-    fn delay_time(&self, list: &ThingList) -> f64 {
-        if self.count > 1 {
-            geometric_mean(&[
-                list.now() - self.chosen,
-                self.mean_interval(),
-                list.mean_interval(),
-            ])
-        } else if self.count == 1 {
-            geometric_mean(&[list.now() - self.chosen, list.mean_interval()])
+            scheduler::geometric_mean(&recent)
+        }
+    }
+    /// How much to scale the delay after choosing the thing, so that it
+    /// comes up less often when it keeps being passed over.  Since each
+    /// choice only halves the effect, it wears off over several choices
+    /// rather than all at once.
+    fn mood_factor(&self) -> f64 {
+        self.mood.exp()
+    }
+    fn pass_count(&self, pass: Pass) -> usize {
+        let older = Pass::ALL.iter().position(|p| *p == pass);
+        self.passes.iter().filter(|p| p.pass == pass).count()
+            + older.map(|i| self.older_passes[i]).unwrap_or(0)
+    }
+    /// The rating of the most recent choice, if it has one.
+    fn last_rating(&self) -> Option<Rating> {
+        self.ratings
+            .last()
+            .filter(|r| r.chosen == self.chosen)
+            .map(|r| r.rating)
+    }
+    fn rating_count(&self, rating: Rating) -> usize {
+        self.ratings.iter().filter(|r| r.rating == rating).count()
+    }
+    /// The mean number of picks between choices of this thing.  In
+    /// real time two choices can come within a second of each other,
+    /// so we count at least one pick, as picks themselves would.
+    fn mean_interval(&self, list: &ThingList) -> f64 {
+        if self.count < 2 {
+            1.0
         } else {
-            list.mean_interval()
-        }
-    }
-}
-
-pub fn read_lists(code: &str) -> Vec<String> {
-    let dir: std::path::PathBuf = format!("data/{}", code).into();
-    match std::fs::read_dir(&dir) {
-        Ok(ddd) => {
-            let mut lists = Vec::new();
-            for entry in ddd {
-                if let Ok(entry) = entry {
-                    if let Some(s) = entry
-                        .path()
-                        .to_str()
-                        .iter()
-                        .flat_map(|x| x.rsplit('/'))
-                        .next()
-                    {
-                        lists.push(s.to_string());
-                    }
-                }
-            }
-            lists
+            let interval = list.elapsed(
+                (self.first_chosen, self.first_chosen_at),
+                (self.chosen, self.chosen_at),
+            ) / (self.count as f64 - 1.0);
+            interval.max(1.0)
         }
-        Err(_) => Vec::new(),
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// Per-list settings, which are saved along with the things.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct ListSettings {
+    /// Measure intervals in wall-clock time rather than in picks.
+    #[serde(default)]
+    real_time: bool,
+    /// When we started keeping wall-clock time for this list.
+    #[serde(default)]
+    started_at: Option<DateTime<Utc>>,
+    /// The value of `ThingList::now` at `started_at`.
+    #[serde(default)]
+    started_now: f64,
+    #[serde(default)]
+    scheduler: scheduler::Kind,
+    #[serde(default)]
+    cooldown: Option<scheduler::Cooldown>,
+    /// How many times the things that have since been deleted were
+    /// chosen, which still count toward `ThingList::now`.
+    #[serde(default)]
+    deleted: u64,
+}
+
+/// The contents of a list, as it is saved.
+#[derive(Serialize, Deserialize)]
+struct ListFile {
+    #[serde(flatten)]
+    settings: ListSettings,
+    things: Vec<Thing>,
+    #[serde(default)]
+    archived: Vec<Thing>,
+}
+
+/// Lists used to be saved as a bare sequence of things, so we accept
+/// either format when reading.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredList {
+    File(ListFile),
+    Things(Vec<Thing>),
+}
+
+impl StoredList {
+    fn into_file(self) -> ListFile {
+        match self {
+            StoredList::File(file) => file,
+            StoredList::Things(things) => ListFile {
+                settings: ListSettings::default(),
+                things,
+                archived: Vec::new(),
+            },
+        }
+    }
+}
+
+/// Everything but the name may be left out when a list is imported,
+/// as a script writing one from scratch would.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct ThingList {
+    #[serde(default)]
     code: String,
     name: String,
+    #[serde(default)]
+    settings: ListSettings,
+    #[serde(default)]
     things: Vec<Thing>,
+    /// Things that are kept for their history, but no longer scheduled.
+    #[serde(default)]
+    archived: Vec<Thing>,
 }
 
+/// The page for one list, which also links to the other lists in the
+/// collection.
+struct ListPage {
+    list: ThingList,
+    others: Vec<String>,
+    undo_len: usize,
+}
 #[with_template("[%" "%]" "things.html")]
-impl DisplayAs<HTML> for ThingList {}
+impl DisplayAs<HTML> for ListPage {}
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+impl ListPage {
+    fn read(store: &dyn Store, code: &str, name: &str) -> Self {
+        ListPage {
+            list: ThingList::read(store, code, name),
+            others: store
+                .lists(code)
+                .into_iter()
+                .filter(|x| x != name)
+                .collect(),
+            undo_len: undo::len(store, code, name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct ThingsOnly(ThingList);
 
 #[with_template("[%" "%]" "things-only.html")]
 impl DisplayAs<HTML> for ThingsOnly {}
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Stats(ThingList);
+
+#[with_template("[%" "%]" "stats.html")]
+impl DisplayAs<HTML> for Stats {}
+
+impl Stats {
+    /// The things, most chosen first.
+    fn by_count(&self) -> Vec<&Thing> {
+        let mut things: Vec<&Thing> = self.0.things.iter().collect();
+        things.sort_by_key(|x| std::cmp::Reverse(x.count));
+        things
+    }
+    /// Describe a number of picks, with the equivalent in days if we
+    /// know how often this list is used.
+    fn picks(&self, picks: f64) -> String {
+        match self.0.picks_per_day() {
+            Some(rate) => format!("{:.1} picks (about {:.1} days)", picks, picks / rate),
+            None => format!("{:.1} picks", picks),
+        }
+    }
+    fn last_chosen(&self, thing: &Thing) -> String {
+        if thing.count == 0 {
+            "never".to_string()
+        } else if let Some(at) = thing.chosen_at {
+            at.format("%Y-%m-%d").to_string()
+        } else {
+            format!("{} ago", self.picks(self.0.now() - thing.chosen))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Archived(ThingList);
+
+#[with_template("[%" "%]" "archived.html")]
+impl DisplayAs<HTML> for Archived {}
+
 impl ThingList {
-    fn read(code: &str, name: &str) -> Self {
-        if let Ok(f) = ::std::fs::File::open(format!("data/{}/{}", code, name)) {
-            if let Ok(mut s) = serde_yaml::from_reader::<_, Vec<Thing>>(&f) {
-                // Anything that has an empty name should just be deleted...
-                s.retain(|x| x.name.len() > 0);
-                // Do not retain any duplicate entries, since that can cause trouble and confusion!
-                let mut to_delete = Vec::new();
-                for (i, thing) in s.iter().enumerate() {
-                    if s[i + 1..].contains(thing) {
-                        to_delete.push(i);
-                    }
-                }
-                for i in to_delete.into_iter().rev() {
-                    s.remove(i);
-                }
-                return ThingList {
-                    code: code.to_string(),
-                    name: name.to_string(),
-                    things: s,
-                };
-            }
+    fn read(store: &dyn Store, code: &str, name: &str) -> Self {
+        if let Some(file) = store.read_list(code, name) {
+            return ThingList::from_stored(code, name, StoredList::File(file));
         }
-        ThingList {
+        let mut list = ThingList {
             code: code.to_string(),
             name: name.to_string(),
+            settings: ListSettings::default(),
             things: Vec::new(),
+            archived: Vec::new(),
+        };
+        list.start_clock();
+        list
+    }
+    /// A list of new things with these names, for tests.
+    #[cfg(test)]
+    fn with_things(names: &[&str]) -> Self {
+        let mut list = ThingList {
+            code: "code".to_string(),
+            name: "list".to_string(),
+            settings: ListSettings::default(),
+            things: Vec::new(),
+            archived: Vec::new(),
+        };
+        for name in names {
+            let thing = list.new_thing(name, 0.0);
+            list.things.push(thing);
         }
+        list
     }
+    /// Make a list from what was saved as `<name>` in `<code>`.  The
+    /// file may have been saved under another code or name, as when it
+    /// comes from a backup, so the things are told where they now are.
+    fn from_stored(code: &str, name: &str, stored: StoredList) -> Self {
+        let (settings, mut s, mut archived) = match stored {
+            StoredList::File(l) => (l.settings, l.things, l.archived),
+            StoredList::Things(s) => (ListSettings::default(), s, Vec::new()),
+        };
+        // Anything that has an empty name should just be deleted...
+        s.retain(|x| !x.name.is_empty());
+        // Do not retain any duplicate entries, since that can cause trouble and confusion!
+        let mut to_delete = Vec::new();
+        for (i, thing) in s.iter().enumerate() {
+            if s[i + 1..].contains(thing) {
+                to_delete.push(i);
+            }
+        }
+        for i in to_delete.into_iter().rev() {
+            s.remove(i);
+        }
+        for thing in s.iter_mut().chain(archived.iter_mut()) {
+            thing.parent_code = code.to_string();
+            thing.parent_name = name.to_string();
+        }
+        let mut list = ThingList {
+            code: code.to_string(),
+            name: name.to_string(),
+            settings,
+            things: s,
+            archived,
+        };
+        list.start_things();
+        list.start_clock();
+        list
+    }
+    /// Note when we start keeping wall-clock time, so we can later
+    /// convert real time into picks.
+    fn start_clock(&mut self) {
+        if self.settings.started_at.is_none() {
+            self.settings.started_at = Some(Utc::now());
+            self.settings.started_now = self.now();
+        }
+    }
+    /// Give the things whose times were left out the times of a thing
+    /// that is new right now.
+    fn start_things(&mut self) {
+        let now = self.now();
+        let now_at = Utc::now();
+        for thing in self.things.iter_mut().chain(self.archived.iter_mut()) {
+            if thing.created.is_nan() {
+                thing.created = now;
+                thing.created_at = thing.created_at.or(Some(now_at));
+            }
+            for time in [&mut thing.first_chosen, &mut thing.chosen, &mut thing.next] {
+                if time.is_nan() {
+                    *time = now;
+                }
+            }
+        }
+    }
+    fn new_thing(&self, name: &str, now: f64) -> Thing {
+        Thing {
+            name: name.to_string(),
+            link: None,
+            tags: Vec::new(),
+            notes: String::new(),
+            cooldown: None,
+            ratings: Vec::new(),
+            passes: Vec::new(),
+            older_passes: [0; 2],
+            mood: 0.0,
+            available: availability::Availability::default(),
+            next: now,
+            first_chosen: now,
+            first_chosen_at: None,
+            chosen: now,
+            chosen_at: None,
+            passed_at: None,
+            created: now,
+            created_at: Some(Utc::now()),
+            count: 0,
+            parent_name: self.name.clone(),
+            parent_code: self.code.clone(),
+        }
+    }
+    /// Keep the things whose names or notes contain `s`.  Any words in `s` that
+    /// start with `#` are instead tags that the things must have.
     fn filter(mut self, s: &str) -> Self {
-        self.things.retain(|x| x.name.contains(s));
+        let tags: Vec<&str> = s
+            .split_whitespace()
+            .filter_map(|w| w.strip_prefix('#'))
+            .filter(|t| !t.is_empty())
+            .collect();
+        let text = if tags.is_empty() {
+            s.to_string()
+        } else {
+            s.split_whitespace()
+                .filter(|w| !w.starts_with('#'))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        self.things.retain(|x| {
+            (x.name.contains(&text) || x.notes.contains(&text))
+                && tags.iter().all(|t| x.tags.iter().any(|xt| xt == t))
+        });
         self
     }
-    fn sorted(mut self) -> Self {
-        self.things.sort_by_key(|x| x.chosen as i64);
-        self.things.sort_by_key(|x| x.count as i64);
-        let now = self.now();
-        for (i, x) in self.things.iter_mut().enumerate() {
-            x.next = now + i as f64;
+    /// The things that are available today, in order.  Things that are
+    /// still cooling down are left out, even if nothing else is ready.
+    fn available_today(&self) -> Vec<&Thing> {
+        self.things.iter().filter(|x| self.shown_today(x)).collect()
+    }
+    /// Whether a thing is among those that can be chosen today.
+    fn shown_today(&self, thing: &Thing) -> bool {
+        thing.available.today() && !self.cooling_down(thing)
+    }
+    /// The things that would be available today, but were chosen too
+    /// recently.
+    fn cooling_down_today(&self) -> Vec<&Thing> {
+        self.things
+            .iter()
+            .filter(|x| x.available.today() && self.cooling_down(x))
+            .collect()
+    }
+    fn unavailable_today(&self) -> Vec<&Thing> {
+        self.things
+            .iter()
+            .filter(|x| !x.available.today())
+            .collect()
+    }
+    /// Every tag used in this list.
+    fn tags(&self) -> std::collections::BTreeSet<&str> {
+        self.things
+            .iter()
+            .flat_map(|x| x.tags.iter())
+            .map(|t| t.as_str())
+            .collect()
+    }
+    /// Put the things in a fresh order as the scheduler would have it,
+    /// setting each `next` to match.  This does not save the list.
+    fn sorted(mut self, rng: &mut dyn RngCore) -> Self {
+        self.scheduler().sort(&mut self, rng);
+        // No sort order may bring something back before its cooldown.
+        for i in 0..self.things.len() {
+            let earliest = self.earliest(&self.things[i]);
+            let x = &mut self.things[i];
+            x.next = x.next.max(earliest);
         }
-        self.save();
+        self.things.sort_by(|a, b| a.next.total_cmp(&b.next));
         self
     }
+    /// Show the things in a different order, leaving the schedule alone.
+    fn sorted_by(mut self, by: SortBy) -> Self {
+        match by {
+            SortBy::Next => self.things.sort_by(|a, b| a.next.total_cmp(&b.next)),
+            SortBy::Count => self.things.sort_by_key(|x| std::cmp::Reverse(x.count)),
+            SortBy::Chosen => self.things.sort_by(|a, b| b.chosen.total_cmp(&a.chosen)),
+            SortBy::Name => self.things.sort_by_key(|x| x.name.to_lowercase()),
+        }
+        self
+    }
+    fn scheduler(&self) -> &'static dyn scheduler::Scheduler {
+        self.settings.scheduler.scheduler()
+    }
     fn now(&self) -> f64 {
-        self.things.iter().map(|x| x.count as f64).sum()
+        // Archived and deleted things still count, so the clock never
+        // runs backwards.
+        self.things
+            .iter()
+            .chain(self.archived.iter())
+            .map(|x| x.count as f64)
+            .sum::<f64>()
+            + self.settings.deleted as f64
     }
     fn mean_interval(&self) -> f64 {
         self.things.len() as f64
     }
-    fn save(&self) {
-        let f = atomicfile::AtomicFile::create(format!("data/{}/{}", self.code, self.name))
-            .expect("error creating save file");
-        serde_yaml::to_writer(&f, &self.things).expect("error writing yaml")
+    /// The average number of picks per day since we started keeping
+    /// wall-clock time.  We need at least a day to go on, or the rate
+    /// would be wildly off.
+    fn picks_per_day(&self) -> Option<f64> {
+        let days = days_between(self.settings.started_at?, Utc::now());
+        let picks = self.now() - self.settings.started_now;
+        if days >= 1.0 && picks > 0.0 {
+            Some(picks / days)
+        } else {
+            None
+        }
+    }
+    /// The time between two moments, measured in picks.  For lists
+    /// that use real time this is the wall-clock interval scaled by the
+    /// typical rate of picking, so a list that is ignored for a month
+    /// still sees a month go by.
+    fn elapsed(&self, from: (f64, Option<DateTime<Utc>>), to: (f64, Option<DateTime<Utc>>)) -> f64 {
+        if !self.settings.real_time {
+            return to.0 - from.0;
+        }
+        if let (Some(rate), Some(from_at), Some(to_at)) = (self.picks_per_day(), from.1, to.1) {
+            days_between(from_at, to_at) * rate
+        } else {
+            to.0 - from.0
+        }
+    }
+    /// The earliest that a thing may come up again, given its cooldown
+    /// or else that of the list.
+    fn earliest(&self, thing: &Thing) -> f64 {
+        match thing.cooldown.or(self.settings.cooldown) {
+            Some(cooldown) if thing.count > 0 => thing.chosen + cooldown.picks(self),
+            _ => f64::NEG_INFINITY,
+        }
+    }
+    /// Whether a thing was chosen too recently to come up again.  A
+    /// cooldown in days is measured by the clock, however often the list
+    /// is used.
+    fn cooling_down(&self, thing: &Thing) -> bool {
+        if thing.count == 0 {
+            return false;
+        }
+        match (thing.cooldown.or(self.settings.cooldown), thing.chosen_at) {
+            (Some(scheduler::Cooldown::Days(days)), Some(at)) => {
+                days_between(at, Utc::now()) < days
+            }
+            (Some(_), _) => self.now() < self.earliest(thing),
+            (None, _) => false,
+        }
+    }
+    fn record(&self, store: &dyn Store, thing: &str, action: history::Action) {
+        history::record(
+            store,
+            &self.code,
+            &self.name,
+            &history::Event::new(thing, self.now(), action),
+        );
+    }
+    /// Save the list, first remembering what was there so the change
+    /// can be undone.
+    fn save(&self, store: &dyn Store) {
+        let file = ListFile {
+            settings: self.settings.clone(),
+            things: self.things.clone(),
+            archived: self.archived.clone(),
+        };
+        if let Some(previous) = store.read_list(&self.code, &self.name) {
+            match undo::Change::between(&previous, &file) {
+                Some(change) => undo::push(store, &self.code, &self.name, &change),
+                None => return,
+            }
+        }
+        store.write_list(&self.code, &self.name, &file);
+    }
+    /// Put the list back the way it was before its last `steps` changes.
+    fn undo(store: &dyn Store, code: &str, name: &str, steps: usize) -> Self {
+        let changes = undo::pop(store, code, name, steps);
+        if changes.is_empty() {
+            return ThingList::read(store, code, name);
+        }
+        let mut file = match store.read_list(code, name) {
+            Some(file) => file,
+            None => return ThingList::read(store, code, name),
+        };
+        for change in changes {
+            change.revert(&mut file);
+        }
+        store.write_list(code, name, &file);
+        let list = ThingList::read(store, code, name);
+        list.record(store, "", history::Action::Undo { steps });
+        list
+    }
+    /// Whether there is a thing of this name, archived or not.
+    fn has(&self, name: &str) -> bool {
+        self.things
+            .iter()
+            .chain(self.archived.iter())
+            .any(|th| th.name == name)
     }
-    fn edit(&mut self, which: &str) -> &mut Thing {
+    /// Add a new thing at the bottom, unless the name is taken.
+    fn add(&mut self, name: &str) -> bool {
+        if self.has(name) {
+            // Adding would leave us with a duplicate.
+            return false;
+        }
+        let now = self.now();
+        let thing = self.new_thing(name, now);
+        self.things.push(thing);
+        true
+    }
+    /// The thing to edit, added if there is none, and renamed to
+    /// `newname`.  We give `None` rather than leave two things with the
+    /// same name, which archived things may not share either.
+    fn edit(&mut self, which: &str, newname: &str) -> Option<&mut Thing> {
+        if newname != which && self.has(newname) {
+            return None;
+        }
         let mut wh = self.things.len();
         for (i, th) in self.things.iter().enumerate() {
             if th.name == which {
@@ -415,50 +1658,123 @@ impl ThingList {
             }
         }
         if wh == self.things.len() {
+            if self.has(which) {
+                // It is archived, and must be restored to be edited.
+                return None;
+            }
             let now = self.now() + 1.0;
-            self.things.push(Thing {
-                name: which.to_string(),
-                link: None,
-                next: now,
-                first_chosen: now,
-                chosen: now,
-                created: now,
-                count: 0,
-                parent_name: self.name.clone(),
-                parent_code: self.code.clone(),
-            });
+            let thing = self.new_thing(which, now);
+            self.things.push(thing);
         }
-        &mut self.things[wh]
+        let th = &mut self.things[wh];
+        th.name = newname.to_string();
+        Some(th)
+    }
+    fn choose(&mut self, which: &str, rng: &mut dyn RngCore) {
+        self.choose_all(&[which], rng);
     }
-    fn choose(&mut self, which: &str) {
+    /// Choose several things at once.  They are all scheduled from the
+    /// same state of the list, in the order of the list, so the order
+    /// they are given in does not matter.  We return the names of the
+    /// things that were chosen, leaving out any that are not in the list.
+    fn choose_all(&mut self, which: &[&str], rng: &mut dyn RngCore) -> Vec<String> {
         // print(
         // 'choosing: ${prettyTime(chosen)}  and  ${prettyDuration(meanInterval)}  and  ${prettyDuration(meanIntervalList)}');
-        let now = self.now() + 1.0;
-        let mut which_num = 0;
-        let mut thing = Thing {
-            name: which.to_string(),
-            link: None,
-            next: now,
-            first_chosen: now,
-            chosen: now,
-            created: now,
-            count: 0,
-            parent_name: self.name.clone(),
-            parent_code: self.code.clone(),
+        let mut chosen: Vec<Thing> = Vec::new();
+        for th in self.things.iter() {
+            if which.contains(&th.name.as_str()) {
+                chosen.push(th.clone());
+            }
+        }
+        let now = self.now() + chosen.len() as f64;
+        let now_at = Utc::now();
+        for thing in chosen.iter_mut() {
+            thing.next = now + self.scheduler().chosen(thing, self, rng);
+            thing.mood /= 2.0;
+            thing.chosen = now;
+            thing.chosen_at = Some(now_at);
+            thing.count += 1;
+            if thing.count == 1 {
+                thing.first_chosen = now;
+                thing.first_chosen_at = Some(now_at);
+            }
+            thing.next = thing.next.max(self.earliest(thing));
+        }
+        self.things.retain(|th| !chosen.contains(th));
+        let names = chosen.iter().map(|x| x.name.clone()).collect();
+        for thing in chosen {
+            self.insert(thing);
+        }
+        names
+    }
+    /// Rate the most recent choice of a thing, adjusting when it will
+    /// next come up to match.
+    fn rate(&mut self, which: &str, rating: Rating) {
+        let i = match self.things.iter().position(|th| th.name == which) {
+            Some(i) if self.things[i].count > 0 => i,
+            _ => return,
         };
-        for (i, th) in self.things.iter().enumerate() {
-            if th.name == which {
-                thing = th.clone();
-                which_num = i;
-                thing.next = now + thing.delay_time(self);
-                thing.chosen = now;
-                thing.count += 1;
-                if thing.count == 1 {
-                    thing.first_chosen = now;
-                }
+        let mut thing = self.things.remove(i);
+        let before = self.scheduler().rated(&thing);
+        if thing.last_rating().is_some() {
+            thing.ratings.pop();
+        }
+        thing.ratings.push(scheduler::Rated {
+            chosen: thing.chosen,
+            chosen_at: thing.chosen_at,
+            rating,
+        });
+        let after = self.scheduler().rated(&thing);
+        thing.next = thing.chosen + (thing.next - thing.chosen) * after / before;
+        thing.next = thing.next.max(self.earliest(&thing));
+        self.insert(thing);
+    }
+    /// Pass over a thing, moving it down the list.  A thing that is not
+    /// wanted is pushed back by the scheduler, while one that only cannot
+    /// be done right now just goes after the thing that was next.
+    fn delay(&mut self, which: &str, pass: Pass, rng: &mut dyn RngCore) {
+        let i = match self.things.iter().position(|th| th.name == which) {
+            Some(i) => i,
+            None => return,
+        };
+        let now = self.now();
+        let passed_at = Utc::now();
+        let thing = &mut self.things[i];
+        thing.passed_at = Some(passed_at);
+        thing.passes.push(scheduler::Passed {
+            passed: now,
+            passed_at: Some(passed_at),
+            pass,
+        });
+        while thing.passes.len() > RECENT_PASSES {
+            let old = thing.passes.remove(0);
+            if let Some(i) = Pass::ALL.iter().position(|p| *p == old.pass) {
+                thing.older_passes[i] += 1;
             }
         }
-        self.things.remove(which_num);
+        // However often it is passed over, it is slowed no more than by
+        // a run of `RECENT_PASSES` passes.
+        let most = RECENT_PASSES as f64 * Pass::NotInTheMood.factor().ln();
+        thing.mood = (thing.mood + pass.factor().ln()).min(most);
+        if i == self.things.len() - 1 {
+            // It is already last, no point delaying!
+            return;
+        }
+        let mut thing = self.things.remove(i);
+        if pass == Pass::NotInTheMood {
+            thing.next += self.scheduler().passed(&thing, self, rng);
+        }
+        // It must end up after the next thing that can be chosen today,
+        // even if the scheduler gives no delay at all.
+        let after = self.things[i..]
+            .iter()
+            .find(|x| self.shown_today(x))
+            .unwrap_or(&self.things[i]);
+        thing.next = thing.next.max(after.next);
+        self.insert(thing);
+    }
+    /// Insert a thing after everything that is due no later than it.
+    fn insert(&mut self, thing: Thing) {
         let mut place = 0;
         for (i, th) in self.things.iter().enumerate() {
             if th.next <= thing.next {
@@ -467,46 +1783,30 @@ impl ThingList {
         }
         self.things.insert(place, thing);
     }
-    fn delay(&mut self, which: &str) {
-        // print(
-        // 'choosing: ${prettyTime(chosen)}  and  ${prettyDuration(meanInterval)}  and  ${prettyDuration(meanIntervalList)}');
-        let now = self.now() + 1.0;
-        let mut which_num = 0;
-        let mut thing = Thing {
-            name: self.name.clone(),
-            link: None,
-            next: now,
-            first_chosen: now,
-            chosen: now,
-            created: now,
-            count: 0,
-            parent_name: self.name.clone(),
-            parent_code: self.code.clone(),
-        };
-
-        for (i, th) in self.things.iter_mut().enumerate() {
-            if th.name == which {
-                thing = th.clone();
-                which_num = i;
-            }
+    fn archive(&mut self, which: &str) {
+        if let Some(i) = self.things.iter().position(|th| th.name == which) {
+            let thing = self.things.remove(i);
+            self.archived.push(thing);
         }
-        if which_num == self.things.len() - 1 {
-            // It is already last, no point delaying!
+    }
+    /// Bring an archived thing back, at the top of the list.
+    fn restore(&mut self, which: &str) {
+        if self.things.iter().any(|th| th.name == which) {
+            // Restoring would leave us with a duplicate.
             return;
         }
-        while self.things[which_num].name == which {
-            // Checking that the thing was actually found and hasn't yet moved
-            self.things.remove(which_num);
-            thing.next += thing.delay_time(self) * (0.1 + 0.9 * rand::random::<f64>());
-            let mut place = 0;
-            for (i, th) in self.things.iter().enumerate() {
-                if th.next <= thing.next {
-                    place = i + 1;
-                }
-            }
-            self.things.insert(place, thing.clone());
+        if let Some(i) = self.archived.iter().position(|th| th.name == which) {
+            let mut thing = self.archived.remove(i);
+            thing.next = self.things.first().map(|th| th.next).unwrap_or(self.now());
+            self.things.insert(0, thing);
         }
     }
+    fn delete(&mut self, which: &str) {
+        let before = self.now();
+        self.things.retain(|th| th.name != which);
+        self.archived.retain(|th| th.name != which);
+        self.settings.deleted += (before - self.now()) as u64;
+    }
 }
 
 impl PartialOrd for Thing {
@@ -533,10 +1833,297 @@ impl PartialEq for Thing {
 
 impl Eq for Thing {}
 
-pub fn geometric_mean(data: &[f64]) -> f64 {
-    let mut product = 1.0;
-    for &d in data.iter() {
-        product *= d;
+/// Split a comma-separated list of tags, ignoring any leading `#`.
+fn parse_tags(tags: &str) -> Vec<String> {
+    let mut tags: Vec<String> = tags
+        .split(',')
+        .map(|t| t.trim().trim_start_matches('#').trim().to_string())
+        .filter(|t| !t.is_empty())
+        .collect();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+fn days_between(start: DateTime<Utc>, end: DateTime<Utc>) -> f64 {
+    (end - start).num_seconds() as f64 / (24.0 * 60.0 * 60.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_without_timestamps_still_load() {
+        let yaml = "- name: soup
+  created: 0.0
+  first_chosen: 1.0
+  chosen: 3.0
+  next: 5.0
+  count: 2
+  parent_code: code
+  parent_name: list
+";
+        match serde_yaml::from_str::<StoredList>(yaml) {
+            Ok(StoredList::Things(things)) => assert_eq!(things[0].chosen_at, None),
+            _ => panic!("an old list did not load"),
+        }
+    }
+
+    #[test]
+    fn real_time_intervals_are_at_least_one_pick() {
+        let now = Utc::now();
+        let mut list = ThingList::with_things(&[]);
+        list.settings = ListSettings {
+            real_time: true,
+            started_at: Some(now - chrono::Duration::days(2)),
+            ..ListSettings::default()
+        };
+        // Chosen ten times, all within the same second.
+        let mut thing = list.new_thing("soup", 0.0);
+        thing.count = 10;
+        thing.first_chosen_at = Some(now);
+        thing.chosen_at = Some(now);
+        list.things.push(thing);
+        assert_eq!(list.things[0].mean_interval(&list), 1.0);
+    }
+
+    #[test]
+    fn restored_things_go_to_the_top() {
+        let mut list = ThingList::with_things(&["first", "second", "third"]);
+        list.archive("third");
+        list.restore("third");
+        let names: Vec<&str> = list.things.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, vec!["third", "first", "second"]);
+    }
+
+    #[test]
+    fn archived_names_are_not_taken_again() {
+        let mut list = ThingList::with_things(&["first", "second"]);
+        list.archive("first");
+        assert!(!list.add("first"));
+        assert!(list.edit("first", "first").is_none());
+        assert!(list.edit("second", "first").is_none());
+        assert!(list.edit("second", "second").is_some());
+        assert!(list.add("third"));
+        assert!(!list.add("third"));
+        let names: Vec<&str> = list.things.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, vec!["second", "third"]);
+        assert_eq!(list.archived.len(), 1);
+    }
+
+    #[test]
+    fn deleting_does_not_turn_back_the_clock() {
+        let mut list = ThingList::with_things(&["a", "b"]);
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..20 {
+            list.choose("a", &mut rng);
+        }
+        for _ in 0..3 {
+            list.choose("b", &mut rng);
+        }
+        let now = list.now();
+        list.delete("a");
+        assert_eq!(list.now(), now);
+        list.choose("b", &mut rng);
+        assert!(list.things[0].next.is_finite());
+        assert!(list.things[0].next > now);
+    }
+
+    #[test]
+    fn searches_can_ask_for_tags() {
+        let mut list = ThingList::with_things(&[]);
+        for (name, tags) in [
+            ("lentil soup", "vegetarian"),
+            ("fish soup", ""),
+            ("salad", "#vegetarian"),
+        ] {
+            let mut thing = list.new_thing(name, 0.0);
+            thing.tags = parse_tags(tags);
+            list.things.push(thing);
+        }
+        let found = |s: &str| -> Vec<String> {
+            list.clone()
+                .filter(s)
+                .things
+                .into_iter()
+                .map(|x| x.name)
+                .collect()
+        };
+        assert_eq!(found("soup #vegetarian"), vec!["lentil soup"]);
+        assert_eq!(found("#vegetarian"), vec!["lentil soup", "salad"]);
+        assert_eq!(found("soup"), vec!["lentil soup", "fish soup"]);
+    }
+
+    #[test]
+    fn notes_are_saved_and_searched() {
+        let mut list = ThingList::with_things(&[]);
+        for (name, notes) in [("pancakes", "- flour\n- eggs"), ("toast", "")] {
+            let mut thing = list.new_thing(name, 0.0);
+            thing.notes = notes.to_string();
+            list.things.push(thing);
+        }
+        let yaml = serde_yaml::to_string(&list.things).unwrap();
+        match serde_yaml::from_str::<StoredList>(&yaml) {
+            Ok(StoredList::Things(things)) => {
+                assert_eq!(things[0].notes, "- flour\n- eggs");
+                assert_eq!(things[1].notes, "");
+            }
+            _ => panic!("the notes did not load"),
+        }
+        let found: Vec<String> = list
+            .filter("eggs")
+            .things
+            .into_iter()
+            .map(|x| x.name)
+            .collect();
+        assert_eq!(found, vec!["pancakes"]);
+    }
+
+    #[test]
+    fn lists_are_saved_in_the_store() {
+        let store = store::Memory::default();
+        let mut list = ThingList::read(&store, "code", "dinner");
+        let thing = list.new_thing("soup", 0.0);
+        list.things.push(thing);
+        list.save(&store);
+        list.record(&store, "soup", history::Action::New);
+        list.archive("soup");
+        list.save(&store);
+        assert_eq!(store.lists("code"), vec!["dinner"]);
+        assert_eq!(ThingList::read(&store, "code", "dinner").archived.len(), 1);
+        assert_eq!(history::read(&store, "code", "dinner").len(), 1);
+
+        let list = ThingList::undo(&store, "code", "dinner", 1);
+        assert_eq!(list.things[0].name, "soup");
+        assert!(list.archived.is_empty());
+    }
+
+    #[test]
+    fn choosing_several_does_not_depend_on_their_order() {
+        let choose = |names: &[&str]| {
+            let mut list = ThingList::with_things(&["first", "second", "third", "fourth"]);
+            let chosen = list.choose_all(names, &mut StdRng::seed_from_u64(1));
+            let things: Vec<(String, f64, f64)> = list
+                .things
+                .into_iter()
+                .map(|x| (x.name, x.chosen, x.next))
+                .collect();
+            (chosen, things)
+        };
+        let (chosen, things) = choose(&["second", "fourth"]);
+        assert_eq!(chosen, vec!["second", "fourth"]);
+        assert_eq!(
+            choose(&["fourth", "second", "second", "fifth"]),
+            (chosen, things)
+        );
+    }
+
+    #[test]
+    fn better_ratings_bring_things_back_sooner() {
+        let next = |rating: Rating| {
+            let mut list = ThingList::with_things(&["soup", "salad", "toast"]);
+            let rng = &mut StdRng::seed_from_u64(0);
+            list.choose("soup", rng);
+            list.choose("salad", rng);
+            list.choose("soup", rng);
+            list.rate("soup", rating);
+            list.things.iter().find(|x| x.name == "soup").unwrap().next
+        };
+        assert!(next(Rating::Loved) < next(Rating::Fine));
+        assert!(next(Rating::Fine) < next(Rating::Meh));
+    }
+
+    #[test]
+    fn rating_again_replaces_the_last_rating() {
+        let mut list = ThingList::with_things(&["soup", "salad"]);
+        let rng = &mut StdRng::seed_from_u64(0);
+        list.choose("soup", rng);
+        let mut once = list.clone();
+        once.rate("soup", Rating::Meh);
+        list.rate("soup", Rating::Loved);
+        list.rate("soup", Rating::Meh);
+        let soup = |list: &ThingList| {
+            list.things
+                .iter()
+                .find(|x| x.name == "soup")
+                .unwrap()
+                .clone()
+        };
+        assert_eq!(soup(&list).ratings, soup(&once).ratings);
+        assert!((soup(&list).next - soup(&once).next).abs() < 1e-9);
+        assert_eq!(soup(&list).rating_count(Rating::Loved), 0);
+    }
+
+    #[test]
+    fn every_rating_is_kept_but_only_recent_ones_count() {
+        let mut list = ThingList::with_things(&["soup"]);
+        let rng = &mut StdRng::seed_from_u64(0);
+        for i in 0..8 {
+            list.choose("soup", rng);
+            list.rate("soup", if i < 3 { Rating::Meh } else { Rating::Loved });
+        }
+        let soup = &list.things[0];
+        assert_eq!(soup.ratings.len(), 8);
+        assert_eq!(soup.rating_count(Rating::Meh), 3);
+        assert_eq!(soup.rating_count(Rating::Loved), 5);
+        assert_eq!(soup.rating_factor(), Rating::Loved.factor());
+    }
+
+    #[test]
+    fn not_now_skips_things_that_are_not_shown() {
+        use chrono::Datelike;
+        let mut list = ThingList::with_things(&["a", "b", "c", "d"]);
+        for (i, thing) in list.things.iter_mut().enumerate() {
+            thing.next = i as f64;
+        }
+        let tomorrow = chrono::Local::now().date_naive().weekday().succ();
+        list.things[1].available.weekdays = vec![tomorrow];
+        list.delay("a", Pass::NotNow, &mut StdRng::seed_from_u64(0));
+        let shown: Vec<&str> = list
+            .available_today()
+            .iter()
+            .map(|x| x.name.as_str())
+            .collect();
+        assert_eq!(shown, vec!["c", "a", "d"]);
+    }
+
+    #[test]
+    fn passing_slows_several_choices() {
+        let mut list = ThingList::with_things(&["soup", "salad"]);
+        let rng = &mut StdRng::seed_from_u64(0);
+        for _ in 0..3 {
+            list.delay("soup", Pass::NotInTheMood, rng);
+        }
+        let soup = |list: &ThingList| {
+            list.things
+                .iter()
+                .find(|x| x.name == "soup")
+                .unwrap()
+                .clone()
+        };
+        let mut factors = vec![soup(&list).mood_factor()];
+        for _ in 0..3 {
+            list.choose("soup", rng);
+            factors.push(soup(&list).mood_factor());
+        }
+        assert!(factors.windows(2).all(|f| f[0] > f[1]), "{:?}", factors);
+        assert!(factors[3] > 1.0);
+    }
+
+    #[test]
+    fn only_recent_passes_are_kept() {
+        let mut list = ThingList::with_things(&["soup", "salad"]);
+        let rng = &mut StdRng::seed_from_u64(0);
+        for _ in 0..20 {
+            list.delay("soup", Pass::NotInTheMood, rng);
+        }
+        list.delay("soup", Pass::NotNow, rng);
+        let soup = list.things.iter().find(|x| x.name == "soup").unwrap();
+        assert_eq!(soup.passes.len(), RECENT_PASSES);
+        assert_eq!(soup.pass_count(Pass::NotInTheMood), 20);
+        assert_eq!(soup.pass_count(Pass::NotNow), 1);
+        let most = Pass::NotInTheMood.factor().powi(RECENT_PASSES as i32);
+        assert!((soup.mood_factor() - most).abs() < 1e-9);
     }
-    product.powf(1.0 / data.len() as f64)
 }
@@ -0,0 +1,25 @@
+//! Free-form notes on a thing, such as ingredients, where the book is,
+//! or the chords of a song.  Notes are written in Markdown, and are
+//! cleaned of anything unsafe before we show them, since they are
+//! inserted straight into the page.
+
+use pulldown_cmark::{html, Parser};
+
+/// Render notes as HTML that is safe to put in a page.
+pub fn render(markdown: &str) -> String {
+    let mut out = String::new();
+    html::push_html(&mut out, Parser::new(markdown));
+    ammonia::clean(&out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markdown_is_rendered_and_sanitized() {
+        let notes = render("*Two* eggs\n\n<script>alert('hi')</script>");
+        assert!(notes.contains("<em>Two</em> eggs"), "{}", notes);
+        assert!(!notes.contains("script"), "{}", notes);
+    }
+}
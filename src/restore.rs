@@ -0,0 +1,396 @@
+//! Restore a collection from the tar archive made by `/backup/<code>`.
+//!
+//! This is run as `thing-lists restore --code CODE --file BACKUP.tar`,
+//! or by uploading the archive to `/restore/<code>`, and a collection
+//! may also be restored from one of the snapshots kept by `snapshot`.
+//! Either way, we first make sure that everything in the archive can be
+//! read and show how it differs from what is there now, and only then
+//! write anything.
+
+use crate::collection::diff;
+use crate::store::{self, Store};
+use crate::{undo, ListFile, StoredList, ThingList};
+use clapme::ClapMe;
+use std::io::Read;
+use warp::reply::Reply;
+
+#[derive(Debug, ClapMe)]
+pub struct Flags {
+    /// The code of the collection to restore into.
+    code: String,
+    /// The archive, as downloaded from /backup/<code>.
+    file: String,
+    /// Keep whatever is not in the archive, rather than removing it.
+    merge: bool,
+    /// Only show what would change.
+    dry_run: bool,
+    /// Restore into this SQLite database rather than into data/.
+    sqlite: Option<String>,
+}
+
+pub fn run(flags: Flags) {
+    let data = std::fs::read(&flags.file).unwrap_or_else(|e| {
+        eprintln!("Unable to read {}: {}", flags.file, e);
+        std::process::exit(1);
+    });
+    let backup = Backup::read(&data).unwrap_or_else(|e| {
+        eprintln!("Not restoring {}: {}", flags.file, e);
+        std::process::exit(1);
+    });
+    let data = store::open(flags.sqlite.as_deref());
+    for line in backup.diff(&*data, &flags.code, flags.merge) {
+        println!("{}", line);
+    }
+    if !flags.dry_run {
+        backup.restore(&*data, &flags.code, flags.merge);
+    }
+}
+
+/// Show how restoring changes the collection, and restore it unless
+/// this is a dry run.
+pub fn reply(
+    store: &dyn Store,
+    code: &str,
+    backup: Result<Backup, String>,
+    merge: bool,
+    dry_run: bool,
+) -> warp::reply::Response {
+    match backup {
+        Ok(backup) => {
+            let mut lines = backup.diff(store, code, merge);
+            if !dry_run {
+                backup.restore(store, code, merge);
+                lines.push("Restored.".to_string());
+            }
+            lines.join("\n").into_response()
+        }
+        Err(e) => warp::reply::with_status(
+            format!("Not restoring: {}", e),
+            warp::http::StatusCode::BAD_REQUEST,
+        )
+        .into_response(),
+    }
+}
+
+/// The files in a backup, named by their paths within the collection.
+#[derive(Debug)]
+pub struct Backup {
+    files: Vec<(String, String)>,
+}
+
+impl Backup {
+    /// Read an archive, making sure that every file in it is one we
+    /// could have written.
+    pub fn read(data: &[u8]) -> Result<Self, String> {
+        Backup::read_with(data, |path| match path.strip_prefix("data/") {
+            Some(name) => Ok(Some(name.to_string())),
+            None => Err(format!("{} is not in data/", path)),
+        })
+    }
+
+    /// Read the collection `code` out of a snapshot of all the data,
+    /// ignoring every other collection.
+    pub fn from_snapshot(data: &[u8], code: &str) -> Result<Self, String> {
+        let prefix = format!("data/{}/", code);
+        Backup::read_with(data, |path| {
+            Ok(path.strip_prefix(&prefix).map(|name| name.to_string()))
+        })
+    }
+
+    /// Read the files in an archive, where `name` gives the name of each
+    /// within the collection, or `None` if we should skip it.
+    fn read_with(
+        data: &[u8],
+        name: impl Fn(&str) -> Result<Option<String>, String>,
+    ) -> Result<Self, String> {
+        let mut archive = tar::Archive::new(data);
+        let mut files = Vec::new();
+        for entry in archive.entries().map_err(|e| e.to_string())? {
+            let mut entry = entry.map_err(|e| e.to_string())?;
+            if entry.header().entry_type().is_dir() {
+                continue;
+            }
+            let path = entry.path().map_err(|e| e.to_string())?;
+            let path = path.to_string_lossy().to_string();
+            let name = match name(path.trim_start_matches("./"))? {
+                Some(name) => name,
+                None => continue,
+            };
+            if !entry.header().entry_type().is_file() {
+                return Err(format!("{} is not a file", path));
+            }
+            let mut contents = Vec::new();
+            entry
+                .read_to_end(&mut contents)
+                .map_err(|e| e.to_string())?;
+            check(&name, &contents).map_err(|e| format!("{}: {}", path, e))?;
+            let contents = String::from_utf8(contents).map_err(|e| format!("{}: {}", path, e))?;
+            files.push((name, contents));
+        }
+        if files.is_empty() {
+            return Err("the archive is empty".to_string());
+        }
+        Ok(Backup { files })
+    }
+
+    /// The names of the lists (and sheets) in the backup.
+    fn lists(&self) -> Vec<&str> {
+        self.files
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .filter(|name| !name.contains('/'))
+            .collect()
+    }
+
+    fn file(&self, name: &str) -> Option<&str> {
+        self.files
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, c)| c.as_str())
+    }
+
+    /// Describe how restoring would change the collection `code`, with
+    /// a line for each list.
+    pub fn diff(&self, store: &dyn Store, code: &str, merge: bool) -> Vec<String> {
+        let existing = store.lists(code);
+        let mut lines = Vec::new();
+        for name in self.lists() {
+            let contents = self.file(name).expect("list is in the backup");
+            let what = match store.read(code, name) {
+                Some(ref c) if c == contents => "unchanged".to_string(),
+                _ if name.ends_with(".yaml") => "replaced".to_string(),
+                _ => {
+                    let stored = serde_yaml::from_str(contents).expect("list was checked");
+                    let restored = ThingList::from_stored(code, name, stored);
+                    let change = diff(
+                        &ThingList::read(store, code, name),
+                        &restored,
+                        !existing.iter().any(|x| x == name),
+                    );
+                    change.describe()
+                }
+            };
+            lines.push(format!("{}: {}", name, what));
+        }
+        for name in existing {
+            if !self.lists().contains(&name.as_str()) {
+                let what = if merge { "kept" } else { "removed" };
+                lines.push(format!("{}: {}", name, what));
+            }
+        }
+        lines
+    }
+
+    /// Put every file in the backup into `code`.  Unless we merge,
+    /// everything that is not in the backup is removed, so that the
+    /// collection is just as it was when it was backed up.  The whole
+    /// collection is replaced at once, so a failure part way leaves it
+    /// just as it was.  Restoring a list is itself a change that can be
+    /// undone.
+    pub fn restore(&self, store: &dyn Store, code: &str, merge: bool) {
+        let before: Vec<(&str, ListFile)> = self
+            .lists()
+            .into_iter()
+            .filter(|name| !name.ends_with(".yaml"))
+            .filter_map(|name| Some((name, store.read_list(code, name)?)))
+            .collect();
+        let mut files = self.files.clone();
+        if merge {
+            for name in store.files(code) {
+                // The changes that could be undone were made to the
+                // lists as they are now, not as they are restored.
+                let undo_of = name
+                    .strip_prefix(".undo/")
+                    .and_then(|n| n.split('/').next());
+                let replaced = undo_of.is_some_and(|list| self.file(list).is_some());
+                if self.file(&name).is_none() && !replaced {
+                    if let Some(contents) = store.read(code, &name) {
+                        files.push((name, contents));
+                    }
+                }
+            }
+        }
+        store.replace(code, &files);
+        for (name, previous) in before {
+            let restored = match store.read_list(code, name) {
+                Some(restored) => restored,
+                None => continue,
+            };
+            if let Some(change) = undo::Change::between(&previous, &restored) {
+                undo::push(store, code, name, &change);
+            }
+        }
+    }
+}
+
+/// Make sure one file from a backup is what it ought to be.
+fn check(name: &str, contents: &[u8]) -> Result<(), String> {
+    store::check_name(name)?;
+    let parts: Vec<&str> = name.split('/').collect();
+    match parts[..] {
+        [".history", _] => std::str::from_utf8(contents)
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+        [".undo", _, "top"] => serde_json::from_slice::<serde_json::Value>(contents)
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+        [".undo", _, _] => serde_json::from_slice::<undo::Change>(contents)
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+        // Character sheets, which need only be YAML since the sheets
+        // are not built at present.
+        [sheet] if sheet.ends_with(".yaml") => {
+            serde_yaml::from_slice::<serde_yaml::Value>(contents)
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        }
+        [list] if !list.starts_with('.') => serde_yaml::from_slice::<StoredList>(contents)
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+        _ => Err("this does not belong in a backup".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut ar = tar::Builder::new(Vec::new());
+        for (name, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            ar.append_data(&mut header, name, contents.as_bytes())
+                .unwrap();
+        }
+        ar.into_inner().unwrap()
+    }
+
+    const LIST: &str = "things:
+- name: soup
+  created: 0.0
+  first_chosen: 0.0
+  chosen: 0.0
+  next: 0.0
+  count: 0
+  parent_code: elsewhere
+  parent_name: meals
+";
+
+    #[test]
+    fn backups_are_checked() {
+        let good = archive(&[
+            ("data/dinner", LIST),
+            ("data/.history/dinner", "{}\n"),
+            ("data/.undo/dinner/top", "{\"pushed\":1,\"len\":1}"),
+            (
+                "data/.undo/dinner/0",
+                "{\"things\":[\"soup\"],\"archived\":[],\"changed\":[]}",
+            ),
+        ]);
+        assert_eq!(Backup::read(&good).unwrap().lists(), vec!["dinner"]);
+        for bad in [
+            archive(&[("data/dinner", "- not a thing")]),
+            archive(&[("data/.undo/dinner/0", "]")]),
+            archive(&[("elsewhere/dinner", LIST)]),
+            archive(&[("data/.secret", "")]),
+            archive(&[]),
+        ] {
+            assert!(Backup::read(&bad).is_err());
+        }
+    }
+
+    #[test]
+    fn snapshots_hold_every_collection() {
+        let snapshot = archive(&[("data/one/dinner", LIST), ("data/two/lunch", LIST)]);
+        let backup = Backup::from_snapshot(&snapshot, "two").unwrap();
+        assert_eq!(backup.lists(), vec!["lunch"]);
+        assert!(Backup::from_snapshot(&snapshot, "three").is_err());
+    }
+
+    /// A collection with two lists, and a backup of it from when it
+    /// had only one, and soup had been chosen three times.
+    fn collection() -> (store::Memory, Backup, String) {
+        let store = store::Memory::default();
+        let mut list = ThingList::read(&store, "code", "dinner");
+        let soup = list.new_thing("soup", 0.0);
+        list.things.push(soup);
+        list.save(&store);
+        ThingList::read(&store, "code", "lunch").save(&store);
+        store.write("code", ".history/lunch", "{}\n");
+
+        let then = store::Memory::default();
+        list.things[0].count = 3;
+        list.save(&then);
+        let dinner = then.read("code", "dinner").unwrap();
+        let backup = Backup::read(&archive(&[
+            ("data/dinner", &dinner),
+            ("data/.history/dinner", "{}\n"),
+        ]))
+        .unwrap();
+        (store, backup, dinner)
+    }
+
+    #[test]
+    fn restoring_replaces_the_collection() {
+        let (store, backup, dinner) = collection();
+        let lines = backup.diff(&store, "code", false);
+        assert_eq!(lines, vec!["dinner: changed soup", "lunch: removed"]);
+        backup.restore(&store, "code", false);
+        let mut files = store.files("code");
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                ".history/dinner",
+                ".undo/dinner/0",
+                ".undo/dinner/top",
+                "dinner"
+            ]
+        );
+        assert_eq!(store.read("code", "dinner"), Some(dinner));
+        assert_eq!(
+            backup.diff(&store, "code", false),
+            vec!["dinner: unchanged"]
+        );
+    }
+
+    #[test]
+    fn merging_keeps_the_rest() {
+        let (store, backup, dinner) = collection();
+        let lines = backup.diff(&store, "code", true);
+        assert_eq!(lines, vec!["dinner: changed soup", "lunch: kept"]);
+        backup.restore(&store, "code", true);
+        let mut files = store.files("code");
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                ".history/dinner",
+                ".history/lunch",
+                ".undo/dinner/0",
+                ".undo/dinner/top",
+                "dinner",
+                "lunch"
+            ]
+        );
+        assert_eq!(store.read("code", "dinner"), Some(dinner));
+    }
+
+    #[test]
+    fn only_the_restore_itself_is_undone() {
+        for merge in [false, true] {
+            let (store, backup, _) = collection();
+            let mut list = ThingList::read(&store, "code", "dinner");
+            list.things[0].count = 1;
+            list.save(&store);
+            let before = store.read("code", "dinner");
+            backup.restore(&store, "code", merge);
+            assert_eq!(undo::len(&store, "code", "dinner"), 1);
+            ThingList::undo(&store, "code", "dinner", 5);
+            assert_eq!(store.read("code", "dinner"), before);
+        }
+    }
+}
@@ -0,0 +1,534 @@
+//! Strategies for deciding when each thing should come up again.
+
+use crate::{Thing, ThingList};
+use chrono::{DateTime, Utc};
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+
+/// A way of scheduling the things in a list.  All times are in the
+/// same units as `ThingList::now`.  Any randomness must come from `rng`,
+/// so that a seeded generator gives reproducible results.
+pub trait Scheduler {
+    /// How long after being chosen `thing` should come up again.
+    fn chosen(&self, thing: &Thing, list: &ThingList, rng: &mut dyn RngCore) -> f64;
+    /// How far to push `thing` back when it is passed over.
+    fn passed(&self, thing: &Thing, list: &ThingList, rng: &mut dyn RngCore) -> f64;
+    /// How much to scale the delay after choosing `thing` to account
+    /// for how much it has been enjoyed, and for how often it has been
+    /// passed over when it was not wanted.
+    fn rated(&self, thing: &Thing) -> f64 {
+        thing.rating_factor() * thing.mood_factor()
+    }
+    /// Put the things in a fresh order, setting each `next` to match.
+    /// By default the least-chosen things come first.
+    fn sort(&self, list: &mut ThingList, _rng: &mut dyn RngCore) {
+        list.things.sort_by_key(|x| x.chosen as i64);
+        list.things.sort_by_key(|x| x.count as i64);
+        let now = list.now();
+        for (i, x) in list.things.iter_mut().enumerate() {
+            x.next = now + i as f64;
+        }
+    }
+}
+
+/// Which scheduler a list uses, as saved with the list.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kind {
+    #[default]
+    GeometricMean,
+    RoundRobin,
+    WeightedRandom,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 3] = [Kind::GeometricMean, Kind::RoundRobin, Kind::WeightedRandom];
+
+    pub fn scheduler(self) -> &'static dyn Scheduler {
+        match self {
+            Kind::GeometricMean => &GeometricMean,
+            Kind::RoundRobin => &RoundRobin,
+            Kind::WeightedRandom => &WeightedRandom,
+        }
+    }
+    /// The name used in forms, which matches the serialized form.
+    pub fn id(self) -> &'static str {
+        match self {
+            Kind::GeometricMean => "geometric-mean",
+            Kind::RoundRobin => "round-robin",
+            Kind::WeightedRandom => "weighted-random",
+        }
+    }
+    pub fn label(self) -> &'static str {
+        match self {
+            Kind::GeometricMean => "Favorites come back sooner",
+            Kind::RoundRobin => "Everything in turn",
+            Kind::WeightedRandom => "Favorites, with some surprises",
+        }
+    }
+}
+
+/// The least time that must pass after a thing is chosen before it may
+/// come up again, whatever the scheduler says.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Cooldown {
+    Picks(f64),
+    Days(f64),
+}
+
+impl Cooldown {
+    /// Read a cooldown from a form, where an empty amount means none.
+    pub fn parse(amount: &str, unit: &str) -> Option<Cooldown> {
+        let amount: f64 = amount.trim().parse().ok()?;
+        if amount <= 0.0 {
+            None
+        } else if unit == "days" {
+            Some(Cooldown::Days(amount))
+        } else {
+            Some(Cooldown::Picks(amount))
+        }
+    }
+    pub fn amount(self) -> f64 {
+        match self {
+            Cooldown::Picks(x) | Cooldown::Days(x) => x,
+        }
+    }
+    pub fn unit(self) -> &'static str {
+        match self {
+            Cooldown::Picks(_) => "picks",
+            Cooldown::Days(_) => "days",
+        }
+    }
+    /// The cooldown in the units of `ThingList::now`.  Days are converted
+    /// using how often the list is used, or taken to be one pick a day
+    /// if we do not know that yet.
+    pub fn picks(self, list: &ThingList) -> f64 {
+        match self {
+            Cooldown::Picks(x) => x,
+            Cooldown::Days(x) => x * list.picks_per_day().unwrap_or(1.0),
+        }
+    }
+}
+
+/// How much a thing was enjoyed, when it was chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rating {
+    Loved,
+    Fine,
+    Meh,
+}
+
+impl Rating {
+    pub const ALL: [Rating; 3] = [Rating::Loved, Rating::Fine, Rating::Meh];
+
+    /// How much this rating scales the delay before the thing comes
+    /// up again.
+    pub fn factor(self) -> f64 {
+        match self {
+            Rating::Loved => 0.7,
+            Rating::Fine => 1.0,
+            Rating::Meh => 1.5,
+        }
+    }
+    /// The name used in URLs, which matches the serialized form.
+    pub fn id(self) -> &'static str {
+        match self {
+            Rating::Loved => "loved",
+            Rating::Fine => "fine",
+            Rating::Meh => "meh",
+        }
+    }
+    pub fn icon(self) -> &'static str {
+        match self {
+            Rating::Loved => "favorite",
+            Rating::Fine => "sentiment_satisfied",
+            Rating::Meh => "sentiment_dissatisfied",
+        }
+    }
+}
+
+impl std::str::FromStr for Rating {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        Rating::ALL.iter().copied().find(|r| r.id() == s).ok_or(())
+    }
+}
+
+/// The rating given to one choice of a thing.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rated {
+    /// The value of `Thing::chosen` for the choice that was rated.
+    pub chosen: f64,
+    #[serde(default)]
+    pub chosen_at: Option<DateTime<Utc>>,
+    pub rating: Rating,
+}
+
+/// Why a thing was passed over.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Pass {
+    /// It cannot be done right now, which says nothing about whether
+    /// it is wanted, so it only moves down past the next thing.
+    NotNow,
+    /// It is not wanted, so it is pushed back and comes up less often.
+    /// Every pass was of this kind before there was a choice.
+    #[default]
+    NotInTheMood,
+}
+
+impl Pass {
+    pub const ALL: [Pass; 2] = [Pass::NotNow, Pass::NotInTheMood];
+
+    /// How much each pass like this scales the delay after the thing is
+    /// next chosen.
+    pub fn factor(self) -> f64 {
+        match self {
+            Pass::NotNow => 1.0,
+            Pass::NotInTheMood => 1.2,
+        }
+    }
+    /// The name used in URLs, which matches the serialized form.
+    pub fn id(self) -> &'static str {
+        match self {
+            Pass::NotNow => "not-now",
+            Pass::NotInTheMood => "not-in-the-mood",
+        }
+    }
+    pub fn icon(self) -> &'static str {
+        match self {
+            Pass::NotNow => "watch_later",
+            Pass::NotInTheMood => "thumb_down",
+        }
+    }
+    pub fn label(self) -> &'static str {
+        match self {
+            Pass::NotNow => "Not right now",
+            Pass::NotInTheMood => "Not in the mood",
+        }
+    }
+}
+
+impl std::str::FromStr for Pass {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        Pass::ALL.iter().copied().find(|p| p.id() == s).ok_or(())
+    }
+}
+
+/// One time a thing was passed over.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Passed {
+    /// The value of `ThingList::now` when the thing was passed over.
+    pub passed: f64,
+    #[serde(default)]
+    pub passed_at: Option<DateTime<Utc>>,
+    pub pass: Pass,
+}
+
+/// The original scheduler: a thing comes back after the geometric mean
+/// of how long it has been since it was last chosen, its own mean
+/// interval, and the mean interval of the whole list.
+pub struct GeometricMean;
+
+impl Scheduler for GeometricMean {
+    fn chosen(&self, thing: &Thing, list: &ThingList, _rng: &mut dyn RngCore) -> f64 {
+        delay_time(thing, list) * self.rated(thing)
+    }
+    fn passed(&self, thing: &Thing, list: &ThingList, rng: &mut dyn RngCore) -> f64 {
+        delay_time(thing, list) * (0.1 + 0.9 * rng.gen::<f64>())
+    }
+}
+
+/// Every thing comes up once before anything comes up again.
+pub struct RoundRobin;
+
+impl Scheduler for RoundRobin {
+    fn chosen(&self, _thing: &Thing, list: &ThingList, _rng: &mut dyn RngCore) -> f64 {
+        list.mean_interval()
+    }
+    fn passed(&self, _thing: &Thing, _list: &ThingList, _rng: &mut dyn RngCore) -> f64 {
+        1.0
+    }
+    fn rated(&self, _thing: &Thing) -> f64 {
+        1.0
+    }
+    fn sort(&self, list: &mut ThingList, _rng: &mut dyn RngCore) {
+        list.things.sort_by_key(|x| x.chosen as i64);
+        let now = list.now();
+        for (i, x) in list.things.iter_mut().enumerate() {
+            x.next = now + i as f64;
+        }
+    }
+}
+
+/// Like `GeometricMean` on average, but each delay is drawn from an
+/// exponential distribution so that the order is less predictable.
+pub struct WeightedRandom;
+
+impl Scheduler for WeightedRandom {
+    fn chosen(&self, thing: &Thing, list: &ThingList, rng: &mut dyn RngCore) -> f64 {
+        delay_time(thing, list) * self.rated(thing) * exponential(rng)
+    }
+    fn passed(&self, thing: &Thing, list: &ThingList, rng: &mut dyn RngCore) -> f64 {
+        delay_time(thing, list) * exponential(rng)
+    }
+    fn sort(&self, list: &mut ThingList, rng: &mut dyn RngCore) {
+        let now = list.now();
+        let mean = list.mean_interval();
+        for x in list.things.iter_mut() {
+            x.next = now + mean * exponential(rng);
+        }
+        list.things.sort_by(|a, b| a.next.total_cmp(&b.next));
+    }
+}
+
+/// A random number with an exponential distribution and a mean of one.
+fn exponential(rng: &mut dyn RngCore) -> f64 {
+    -(1.0 - rng.gen::<f64>()).ln()
+}
+
+fn delay_time(thing: &Thing, list: &ThingList) -> f64 {
+    let since_chosen = list.elapsed(
+        (thing.chosen, thing.chosen_at),
+        (list.now(), Some(Utc::now())),
+    );
+    if thing.count > 1 {
+        geometric_mean(&[
+            since_chosen,
+            thing.mean_interval(list),
+            list.mean_interval(),
+        ])
+    } else if thing.count == 1 {
+        geometric_mean(&[since_chosen, list.mean_interval()])
+    } else {
+        list.mean_interval()
+    }
+}
+
+pub fn geometric_mean(data: &[f64]) -> f64 {
+    let mut product = 1.0;
+    for &d in data.iter() {
+        product *= d;
+    }
+    product.powf(1.0 / data.len() as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// A list of `n` things, of which the first few have been chosen.
+    fn list(n: usize) -> ThingList {
+        let names: Vec<String> = (0..n).map(|i| format!("thing {}", i)).collect();
+        let names: Vec<&str> = names.iter().map(|x| x.as_str()).collect();
+        let mut list = ThingList::with_things(&names);
+        let mut rng = StdRng::seed_from_u64(0);
+        for i in 0..3 * n {
+            let name = list.things[i % 2].name.clone();
+            list.choose(&name, &mut rng);
+        }
+        list
+    }
+
+    fn chosen_thing(list: &ThingList) -> &Thing {
+        list.things
+            .iter()
+            .find(|x| x.count > 1)
+            .expect("nothing chosen")
+    }
+
+    #[test]
+    fn geometric_mean_pass_is_within_range() {
+        let list = list(5);
+        let thing = chosen_thing(&list);
+        let most = delay_time(thing, &list);
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..100 {
+            let delay = GeometricMean.passed(thing, &list, &mut rng);
+            assert!(
+                delay >= 0.1 * most && delay <= most,
+                "{} of {}",
+                delay,
+                most
+            );
+        }
+    }
+
+    #[test]
+    fn delays_are_reproducible() {
+        let list = list(5);
+        let thing = chosen_thing(&list);
+        for scheduler in [&GeometricMean as &dyn Scheduler, &WeightedRandom] {
+            let delays = |seed| {
+                let mut rng = StdRng::seed_from_u64(seed);
+                (0..10)
+                    .map(|_| {
+                        (
+                            scheduler.passed(thing, &list, &mut rng),
+                            scheduler.chosen(thing, &list, &mut rng),
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(delays(7), delays(7));
+            assert_ne!(delays(7), delays(8));
+        }
+    }
+
+    #[test]
+    fn weighted_random_delays_are_positive() {
+        let list = list(5);
+        let thing = chosen_thing(&list);
+        let mut rng = StdRng::seed_from_u64(2);
+        for _ in 0..100 {
+            assert!(WeightedRandom.passed(thing, &list, &mut rng) >= 0.0);
+            assert!(WeightedRandom.chosen(thing, &list, &mut rng) >= 0.0);
+        }
+    }
+
+    #[test]
+    fn round_robin_takes_turns() {
+        let mut list = list(4);
+        list.settings.scheduler = Kind::RoundRobin;
+        let mut rng = StdRng::seed_from_u64(12);
+        list = list.sorted(&mut rng);
+        let mut picks = Vec::new();
+        for _ in 0..12 {
+            let name = list.things[0].name.clone();
+            list.choose(&name, &mut rng);
+            picks.push(name);
+        }
+        for name in picks[..4].iter() {
+            assert_eq!(picks.iter().filter(|x| *x == name).count(), 3);
+        }
+        assert_eq!(picks[..4], picks[4..8]);
+        assert_eq!(picks[..4], picks[8..]);
+    }
+
+    #[test]
+    fn round_robin_puts_the_least_recent_first() {
+        let mut list = list(5);
+        list.settings.scheduler = Kind::RoundRobin;
+        let list = list.sorted(&mut StdRng::seed_from_u64(13));
+        assert!(list.things.windows(2).all(|x| x[0].chosen <= x[1].chosen));
+        assert!(list.things.windows(2).all(|x| x[0].next < x[1].next));
+        let thing = chosen_thing(&list);
+        let mut rated = thing.clone();
+        rated.ratings.push(Rated {
+            chosen: rated.chosen,
+            chosen_at: None,
+            rating: Rating::Meh,
+        });
+        assert_eq!(RoundRobin.rated(&rated), 1.0);
+    }
+
+    #[test]
+    fn weighted_random_averages_the_geometric_mean() {
+        let list = list(5);
+        let thing = chosen_thing(&list);
+        let mut rng = StdRng::seed_from_u64(14);
+        let n = 10000;
+        let mean = (0..n)
+            .map(|_| WeightedRandom.chosen(thing, &list, &mut rng))
+            .sum::<f64>()
+            / n as f64;
+        let expected = GeometricMean.chosen(thing, &list, &mut rng);
+        assert!(
+            (mean - expected).abs() < 0.05 * expected,
+            "{} is not near {}",
+            mean,
+            expected
+        );
+    }
+
+    #[test]
+    fn weighted_random_sorts_by_next() {
+        let mut list = list(8);
+        list.settings.scheduler = Kind::WeightedRandom;
+        let sorted = |seed| {
+            let list = list.clone().sorted(&mut StdRng::seed_from_u64(seed));
+            assert!(list.things.windows(2).all(|x| x[0].next <= x[1].next));
+            list.things.into_iter().map(|x| x.name).collect::<Vec<_>>()
+        };
+        assert_eq!(sorted(15), sorted(15));
+        assert_ne!(sorted(15), sorted(16));
+    }
+
+    #[test]
+    fn passing_is_reproducible() {
+        let passed = |seed| {
+            let mut list = list(6);
+            let name = list.things[0].name.clone();
+            list.delay(&name, Pass::NotInTheMood, &mut StdRng::seed_from_u64(seed));
+            list.things
+                .iter()
+                .map(|x| (x.name.clone(), x.next))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(passed(3), passed(3));
+        assert_ne!(passed(3)[0].0, list(6).things[0].name);
+    }
+
+    #[test]
+    fn passing_always_moves_a_thing() {
+        let mut list = list(4);
+        // Just chosen, so the geometric mean gives no delay at all, but
+        // brought back to the top.
+        let name = list.things[3].name.clone();
+        list.choose(&name, &mut StdRng::seed_from_u64(4));
+        let i = list.things.iter().position(|x| x.name == name).unwrap();
+        let mut thing = list.things.remove(i);
+        thing.next = list.things[0].next;
+        list.things.insert(0, thing);
+        let after = list.things[1].name.clone();
+        list.delay(&name, Pass::NotInTheMood, &mut StdRng::seed_from_u64(5));
+        let j = list.things.iter().position(|x| x.name == name).unwrap();
+        let k = list.things.iter().position(|x| x.name == after).unwrap();
+        assert!(j > k, "left {} before {}", name, after);
+    }
+
+    #[test]
+    fn cooling_down_things_are_not_suggested() {
+        let mut list = list(0);
+        list.settings.cooldown = Some(Cooldown::Picks(3.0));
+        for name in ["first", "second"] {
+            let thing = list.new_thing(name, 0.0);
+            list.things.push(thing);
+        }
+        let mut rng = StdRng::seed_from_u64(6);
+        list.choose("first", &mut rng);
+        list.choose("second", &mut rng);
+        // Only two picks have gone by, so neither may come up yet.
+        assert!(list.available_today().is_empty());
+        assert_eq!(list.cooling_down_today().len(), 2);
+    }
+
+    #[test]
+    fn not_now_only_moves_past_the_next_thing() {
+        let mut list = list(5);
+        let name = list.things[0].name.clone();
+        let second = list.things[1].clone();
+        list.delay(&name, Pass::NotNow, &mut StdRng::seed_from_u64(9));
+        assert_eq!(list.things[0].name, second.name);
+        assert_eq!(list.things[1].name, name);
+        assert_eq!(list.things[1].next, second.next);
+    }
+
+    #[test]
+    fn not_in_the_mood_slows_the_next_choice() {
+        let chosen = |pass| {
+            let mut list = list(5);
+            let name = list.things[0].name.clone();
+            list.delay(&name, pass, &mut StdRng::seed_from_u64(10));
+            let thing = list.things.iter().find(|x| x.name == name).unwrap();
+            assert_eq!(thing.passes.len(), 1);
+            GeometricMean.chosen(thing, &list, &mut StdRng::seed_from_u64(11))
+        };
+        assert!(chosen(Pass::NotInTheMood) > chosen(Pass::NotNow));
+    }
+}
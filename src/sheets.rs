@@ -1,7 +1,8 @@
-use crate::atomicfile;
+use crate::store::Store;
 use display_as::{display, with_template, DisplayAs, HTML, UTF8};
 use futures::{FutureExt, StreamExt};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use tokio::sync::{mpsc, RwLock};
 use warp::reply::Reply;
 use warp::{path, Filter};
@@ -121,9 +122,9 @@ struct Item {
 }
 
 impl Character {
-    fn read(code: &str, name: &str) -> Self {
-        if let Ok(f) = ::std::fs::File::open(format!("data/{}/{}.yaml", code, name)) {
-            if let Ok(c) = serde_yaml::from_reader::<_, Character>(&f) {
+    fn read(store: &dyn Store, code: &str, name: &str) -> Self {
+        if let Some(contents) = store.read(code, &format!("{}.yaml", name)) {
+            if let Ok(c) = serde_yaml::from_str::<Character>(&contents) {
                 return c;
             }
         }
@@ -133,11 +134,9 @@ impl Character {
             sections: Vec::new(),
         }
     }
-    fn save(&self) {
-        std::fs::create_dir_all(format!("data/{}", self.code)).unwrap();
-        let f = atomicfile::AtomicFile::create(format!("data/{}/{}.yaml", self.code, self.name))
-            .expect("error creating save file");
-        serde_yaml::to_writer(&f, self).expect("error writing yaml")
+    fn save(&self, store: &dyn Store) {
+        let contents = serde_yaml::to_string(self).expect("error writing yaml");
+        store.write(&self.code, &format!("{}.yaml", self.name), &contents);
     }
     fn change(&mut self, change: &Change) -> Option<Change> {
         for c in self.sections.iter_mut() {
@@ -243,10 +242,13 @@ type Editors = std::sync::Arc<
     >,
 >;
 
-pub fn sheets() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+pub fn sheets(
+    store: Arc<dyn Store>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     let editors = Editors::default();
     // Turns our "state" into a new filter.
     let editors = warp::any().map(move || editors.clone());
+    let store = warp::any().map(move || store.clone());
 
     let css = path!("sheets" / "character.css")
         .or(path!("sheets" / String / "character.css"))
@@ -263,16 +265,17 @@ pub fn sheets() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejecti
         println!("I am doing index.");
         display(HTML, &Index).into_response()
     });
-    let character =
-        path!("sheets" / String / String).map(|code: String, character_name: String| {
+    let character = path!("sheets" / String / String).and(store.clone()).map(
+        |code: String, character_name: String, store: Arc<dyn Store>| {
             let character_name = percent_encoding::percent_decode(character_name.as_bytes())
                 .decode_utf8()
                 .unwrap();
             let code = percent_encoding::percent_decode(code.as_bytes())
                 .decode_utf8()
                 .unwrap();
-            display(HTML, &Character::read(&code, &character_name)).into_response()
-        });
+            display(HTML, &Character::read(&*store, &code, &character_name)).into_response()
+        },
+    );
     // let party = path!("sheets" / String).map(|code: String| {
     //     println!("Party: {}", code);
     //     let code = percent_encoding::percent_decode(code.as_bytes())
@@ -284,8 +287,9 @@ pub fn sheets() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejecti
     let sock = path!("sheets" / "ws" / String / String)
         .and(warp::ws())
         .and(editors)
+        .and(store)
         .map(
-            |code: String, character: String, ws: warp::ws::Ws, editors| {
+            |code: String, character: String, ws: warp::ws::Ws, editors, store: Arc<dyn Store>| {
                 let character = percent_encoding::percent_decode(character.as_bytes())
                     .decode_utf8()
                     .unwrap()
@@ -294,13 +298,14 @@ pub fn sheets() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejecti
                     .decode_utf8()
                     .unwrap()
                     .to_string();
-                ws.on_upgrade(move |socket| editor_connected(code, character, socket, editors))
+                ws.on_upgrade(move |socket| editor_connected(store, code, character, socket, editors))
             },
         );
     sock.or(css).or(character).or(index)
 }
 
 async fn editor_connected(
+    store: Arc<dyn Store>,
     code: String,
     character: String,
     ws: warp::ws::WebSocket,
@@ -345,7 +350,7 @@ async fn editor_connected(
                 break;
             }
         };
-        process_message(&code, &character, msg, &editors).await;
+        process_message(&*store, &code, &character, msg, &editors).await;
     }
 
     // user_ws_rx stream will keep processing as long as the user stays
@@ -362,13 +367,14 @@ struct Change {
 }
 
 async fn process_message(
+    store: &dyn Store,
     code: &str,
     character: &str,
     mut msg: warp::ws::Message,
     editors: &Editors,
 ) {
     let place = format!("{}/{}", code, character);
-    let mut character = Character::read(code, character);
+    let mut character = Character::read(store, code, character);
     match msg.to_str().map(|s| serde_json::from_str(s)) {
         Err(e) => {
             eprintln!("Bad UTF8: {:?} {:?}", e, msg);
@@ -380,7 +386,7 @@ async fn process_message(
             if let Some(newc) = character.change(&change) {
                 msg = warp::ws::Message::text(serde_json::to_string(&newc).unwrap());
             }
-            character.save();
+            character.save(store);
             for tx in editors.read().await.get(&place).unwrap().iter() {
                 if let Err(_disconnected) = tx.send(Ok(msg.clone())) {
                     // The tx is disconnected, our `user_disconnected` code
@@ -0,0 +1,151 @@
+//! Replay a synthetic user against a list, to see what variety the
+//! scheduler actually gives.
+//!
+//! This is run as `thing-lists simulate --code CODE --list LIST ...`.
+//! The list is read from `data/<code>/<list>`, or from the database
+//! given by `--sqlite`, but never saved.
+//!
+//! Every simulated pick happens at once, so a list that measures time
+//! by the clock would see no time pass between them.  We therefore
+//! always simulate a list as if it measured time in picks.
+
+use crate::scheduler::Pass;
+use crate::store;
+use crate::ThingList;
+use clapme::ClapMe;
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+
+#[derive(Debug, ClapMe)]
+pub struct Flags {
+    /// The code of the collection holding the list.
+    code: String,
+    /// The name of the list.
+    list: String,
+    /// How many picks or passes to simulate (default 1000).
+    steps: Option<usize>,
+    /// Seed for the random number generator, to make runs reproducible.
+    seed: Option<u64>,
+    /// Always pick the thing at the top of the list (the default).
+    top: bool,
+    /// Pick at random from this many things at the top of the list.
+    top_k: Option<usize>,
+    /// Pass on the top thing with this probability, otherwise pick it.
+    pass: Option<f64>,
+    /// Read the list from this SQLite database rather than from data/.
+    sqlite: Option<String>,
+}
+
+/// How the simulated user behaves.  These are separate flags rather
+/// than an optional enum, which clapme cannot parse when it is left out.
+#[derive(Debug)]
+enum User {
+    /// Always pick the thing at the top of the list.
+    Top,
+    /// Pick at random from this many things at the top of the list.
+    TopK(usize),
+    /// Pass on the top thing with this probability, otherwise pick it.
+    Pass(f64),
+}
+
+pub fn run(flags: Flags) {
+    let mut list = ThingList::read(
+        &*store::open(flags.sqlite.as_deref()),
+        &flags.code,
+        &flags.list,
+    );
+    if list.things.is_empty() {
+        eprintln!("There is no list {}/{}", flags.code, flags.list);
+        std::process::exit(1);
+    }
+    list.settings.real_time = false;
+    let mut rng = match flags.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let user = match (flags.top, flags.top_k, flags.pass) {
+        (false, Some(k), None) => User::TopK(k),
+        (false, None, Some(p)) => User::Pass(p),
+        (_, None, None) => User::Top,
+        _ => {
+            eprintln!("Give at most one of --top, --top-k and --pass");
+            std::process::exit(1);
+        }
+    };
+    let mut picks = simulate(&mut list, &user, flags.steps.unwrap_or(1000), &mut rng);
+    let total: usize = picks.iter().map(|x| x.1).sum();
+    picks.sort_by_key(|x| std::cmp::Reverse(x.1));
+    println!("{} picks from {} things", total, picks.len());
+    for (name, n) in picks {
+        println!(
+            "{:6} {:5.1}%  {}",
+            n,
+            100.0 * n as f64 / total.max(1) as f64,
+            name
+        );
+    }
+}
+
+/// Let `user` pick or pass `steps` times, returning how often each
+/// thing was picked, in the order the list started in.
+fn simulate(
+    list: &mut ThingList,
+    user: &User,
+    steps: usize,
+    rng: &mut dyn RngCore,
+) -> Vec<(String, usize)> {
+    let mut picks: Vec<(String, usize)> = list.things.iter().map(|x| (x.name.clone(), 0)).collect();
+    for _ in 0..steps {
+        let which = match *user {
+            User::Top => 0,
+            User::TopK(k) => rng.gen_range(0, k.max(1).min(list.things.len())),
+            User::Pass(p) => {
+                if rng.gen::<f64>() < p {
+                    let name = list.things[0].name.clone();
+                    list.delay(&name, Pass::NotInTheMood, rng);
+                    continue;
+                }
+                0
+            }
+        };
+        let name = list.things[which].name.clone();
+        list.choose(&name, rng);
+        if let Some(x) = picks.iter_mut().find(|x| x.0 == name) {
+            x.1 += 1;
+        }
+    }
+    picks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn picks(user: User, seed: u64) -> Vec<(String, usize)> {
+        let mut list = ThingList::with_things(&["soup", "salad", "toast", "pie"]);
+        simulate(&mut list, &user, 100, &mut StdRng::seed_from_u64(seed))
+    }
+
+    #[test]
+    fn every_thing_gets_picked() {
+        for user in [User::Top, User::TopK(3), User::Pass(0.5)] {
+            let picks = picks(user, 0);
+            assert_eq!(picks.len(), 4);
+            assert!(picks.iter().all(|x| x.1 > 0), "{:?}", picks);
+        }
+        let total: usize = picks(User::Top, 0).iter().map(|x| x.1).sum();
+        assert_eq!(total, 100);
+    }
+
+    #[test]
+    fn passing_is_not_picking() {
+        let total: usize = picks(User::Pass(0.5), 1).iter().map(|x| x.1).sum();
+        assert!(total > 0 && total < 100, "{}", total);
+        assert!(picks(User::Pass(1.0), 1).iter().all(|x| x.1 == 0));
+    }
+
+    #[test]
+    fn a_seed_gives_the_same_picks() {
+        assert_eq!(picks(User::TopK(3), 7), picks(User::TopK(3), 7));
+    }
+}
@@ -0,0 +1,226 @@
+//! Regular snapshots of all the data, so that a bad edit can be undone
+//! even when nobody thought to download a backup.
+//!
+//! Each snapshot is a tar archive of all the data, laid out just as a
+//! `/backup/<code>` archive is but holding every collection, and saved
+//! as `snapshots/data-<time>.tar`.  After taking a snapshot we prune the
+//! old ones, keeping the newest in each of the most recent hours, days
+//! and weeks, as many of each as the retention policy asks for.
+
+use crate::atomicfile;
+use crate::store::{self, Store};
+use chrono::{DateTime, Datelike, NaiveDateTime, Utc};
+use clapme::ClapMe;
+use serde::Serialize;
+use std::io::Write;
+use std::sync::Arc;
+
+const DIR: &str = "snapshots";
+const FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// How often to take snapshots, and how many to keep.
+#[derive(Debug, Clone, ClapMe, Serialize)]
+pub struct Flags {
+    /// Minutes between snapshots of the data, or 0 for none (default 60).
+    snapshot_minutes: Option<u64>,
+    /// How many hourly snapshots to keep (default 24).
+    keep_hourly: Option<usize>,
+    /// How many daily snapshots to keep (default 7).
+    keep_daily: Option<usize>,
+    /// How many weekly snapshots to keep (default 8).
+    keep_weekly: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retention {
+    pub hourly: usize,
+    pub daily: usize,
+    pub weekly: usize,
+}
+
+impl Flags {
+    fn retention(&self) -> Retention {
+        Retention {
+            hourly: self.keep_hourly.unwrap_or(24),
+            daily: self.keep_daily.unwrap_or(7),
+            weekly: self.keep_weekly.unwrap_or(8),
+        }
+    }
+}
+
+/// Take a snapshot every so often, for as long as the server runs.
+pub async fn run(flags: Flags, data: Arc<dyn Store>) {
+    let minutes = flags.snapshot_minutes.unwrap_or(60);
+    if minutes == 0 {
+        return;
+    }
+    let retention = flags.retention();
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(60 * minutes));
+    loop {
+        interval.tick().await;
+        let data = data.clone();
+        let done = tokio::task::spawn_blocking(move || {
+            take(&*data, Utc::now());
+            prune(retention);
+        })
+        .await;
+        if let Err(e) = done {
+            println!("error taking a snapshot: {}", e);
+        }
+    }
+}
+
+/// One snapshot on disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    /// The file name, which is how a snapshot is chosen for restoring.
+    pub name: String,
+    pub taken: DateTime<Utc>,
+    pub size: u64,
+}
+
+impl Snapshot {
+    /// Read a snapshot, provided `name` is one of ours.
+    pub fn read(name: &str) -> Option<Vec<u8>> {
+        let snapshot = list().into_iter().find(|x| x.name == name)?;
+        std::fs::read(path(&snapshot.name)).ok()
+    }
+}
+
+fn path(name: &str) -> std::path::PathBuf {
+    format!("{}/{}", DIR, name).into()
+}
+
+/// Every snapshot, newest first.
+pub fn list() -> Vec<Snapshot> {
+    let mut snapshots: Vec<Snapshot> = std::fs::read_dir(DIR)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let time = name.strip_prefix("data-")?.strip_suffix(".tar")?;
+            let taken = NaiveDateTime::parse_from_str(time, FORMAT).ok()?;
+            Some(Snapshot {
+                taken: DateTime::from_naive_utc_and_offset(taken, Utc),
+                size: entry.metadata().ok()?.len(),
+                name,
+            })
+        })
+        .collect();
+    snapshots.sort_by_key(|x| std::cmp::Reverse(x.taken));
+    snapshots
+}
+
+/// Save a snapshot of every collection.
+fn take(data: &dyn Store, now: DateTime<Utc>) {
+    let codes = data.collections();
+    if codes.is_empty() {
+        return;
+    }
+    let mut output = Vec::new();
+    {
+        let mut ar = tar::Builder::new(&mut output);
+        for code in codes {
+            store::archive(data, &code, &format!("data/{}", code), &mut ar)
+                .expect("error archiving data");
+        }
+        ar.finish().expect("error archiving data");
+    }
+    let name = format!("data-{}.tar", now.format(FORMAT));
+    let f = atomicfile::AtomicFile::create(path(&name)).expect("error creating snapshot");
+    (&f).write_all(&output).expect("error writing snapshot");
+    println!("took snapshot {}", name);
+}
+
+/// Remove the snapshots that the retention policy does not keep.
+fn prune(retention: Retention) {
+    let snapshots = list();
+    let taken: Vec<DateTime<Utc>> = snapshots.iter().map(|x| x.taken).collect();
+    for (snapshot, keep) in snapshots.iter().zip(keep(&taken, retention)) {
+        if !keep {
+            std::fs::remove_file(path(&snapshot.name)).ok();
+        }
+    }
+}
+
+/// Which of the snapshots taken at `taken`, newest first, we keep.  A
+/// snapshot is kept if it is the newest in one of the most recent
+/// `retention.hourly` hours that have a snapshot, or likewise for days
+/// or weeks.
+fn keep(taken: &[DateTime<Utc>], retention: Retention) -> Vec<bool> {
+    let mut keep = vec![false; taken.len()];
+    let periods = [
+        (retention.hourly, hour as fn(&DateTime<Utc>) -> String),
+        (retention.daily, day),
+        (retention.weekly, week),
+    ];
+    for (count, period) in periods {
+        let mut seen: Vec<String> = Vec::new();
+        for (i, t) in taken.iter().enumerate() {
+            if seen.len() == count {
+                break;
+            }
+            let p = period(t);
+            if !seen.contains(&p) {
+                seen.push(p);
+                keep[i] = true;
+            }
+        }
+    }
+    keep
+}
+
+fn hour(t: &DateTime<Utc>) -> String {
+    t.format("%Y%m%d%H").to_string()
+}
+
+fn day(t: &DateTime<Utc>) -> String {
+    t.format("%Y%m%d").to_string()
+}
+
+fn week(t: &DateTime<Utc>) -> String {
+    let week = t.iso_week();
+    format!("{}-{}", week.year(), week.week())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn retention_keeps_the_newest_in_each_period() {
+        let now = DateTime::parse_from_rfc3339("2026-10-18T12:30:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        // Every half hour for three weeks.
+        let taken: Vec<DateTime<Utc>> = (0..3 * 7 * 48)
+            .map(|i| now - Duration::minutes(30 * i))
+            .collect();
+        let retention = Retention {
+            hourly: 3,
+            daily: 2,
+            weekly: 2,
+        };
+        let kept: Vec<DateTime<Utc>> = taken
+            .iter()
+            .zip(keep(&taken, retention))
+            .filter(|(_, keep)| *keep)
+            .map(|(t, _)| *t)
+            .collect();
+        let at = |s: &str| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        assert_eq!(
+            kept,
+            vec![
+                at("2026-10-18T12:30:00Z"),
+                at("2026-10-18T11:30:00Z"),
+                at("2026-10-18T10:30:00Z"),
+                // The end of yesterday.
+                at("2026-10-17T23:30:00Z"),
+                // The end of last week, on Sunday the 11th.
+                at("2026-10-11T23:30:00Z"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,225 @@
+//! Import and export a list as CSV, so that things can be kept in (or
+//! bulk-loaded from) a spreadsheet.
+//!
+//! The columns are `name`, `link`, `count` and `tags`, in any order.
+//! Only `name` is required, other columns are ignored, and the tags in
+//! a cell are separated by commas.
+
+use crate::store::Store;
+use crate::{history, parse_tags, read_multipart, ThingList};
+use warp::multipart::FormData;
+
+/// Write the things in a list as CSV, in the order of the list.
+pub fn export(list: &ThingList) -> String {
+    let mut w = csv::Writer::from_writer(Vec::new());
+    w.write_record(["name", "link", "count", "tags"])
+        .expect("error writing csv");
+    for thing in list.things.iter() {
+        w.write_record([
+            thing.name.as_str(),
+            thing.link.as_deref().unwrap_or(""),
+            &thing.count.to_string(),
+            &thing.tags.join(", "),
+        ])
+        .expect("error writing csv");
+    }
+    String::from_utf8(w.into_inner().expect("error writing csv")).expect("csv is not utf8")
+}
+
+/// A CSV file uploaded to be added to a list.
+#[derive(Debug, Default)]
+pub struct Import {
+    pub code: String,
+    pub list: String,
+    /// Update the things already in the list that have the same name.
+    /// Without this, importing a name that is already in the list (even
+    /// if archived) is an error, so that nothing is added twice.
+    pub merge: bool,
+    pub csv: Vec<u8>,
+}
+
+impl Import {
+    pub async fn from_form(form: FormData) -> Result<Self, warp::Error> {
+        let parts = read_multipart(form).await?;
+        let mut import = Import::default();
+        for (name, data) in parts {
+            match name.as_str() {
+                "code" => import.code = String::from_utf8_lossy(&data).to_string(),
+                "list" => import.list = String::from_utf8_lossy(&data).to_string(),
+                "merge" => import.merge = true,
+                "csv" => import.csv = data,
+                _ => (),
+            }
+        }
+        Ok(import)
+    }
+    /// Add the rows to the list and save it, returning how many rows
+    /// there were.
+    pub fn import(&self, store: &dyn Store) -> Result<usize, String> {
+        let mut list = ThingList::read(store, &self.code, &self.list);
+        let rows = apply(&mut list, &self.csv, self.merge)?;
+        list.save(store);
+        for (name, is_new) in rows.iter() {
+            let action = if *is_new {
+                history::Action::New
+            } else {
+                let th = list
+                    .things
+                    .iter()
+                    .chain(list.archived.iter())
+                    .find(|x| &x.name == name);
+                history::Action::Edit {
+                    newname: name.clone(),
+                    link: th.and_then(|x| x.link.clone()),
+                    tags: th.map(|x| x.tags.clone()).unwrap_or_default(),
+                }
+            };
+            list.record(store, name, action);
+        }
+        Ok(rows.len())
+    }
+}
+
+/// Add the rows of `csv` to `list`, returning the name of the thing in
+/// each row and whether it is new.  An empty cell leaves that field of
+/// an existing thing alone, and an archived thing stays archived.
+fn apply(list: &mut ThingList, csv: &[u8], merge: bool) -> Result<Vec<(String, bool)>, String> {
+    let mut r = csv::ReaderBuilder::new().flexible(true).from_reader(csv);
+    let headers = r.headers().map_err(|e| e.to_string())?.clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h.trim().eq_ignore_ascii_case(name))
+    };
+    let name_column = column("name").ok_or("there is no name column")?;
+    let (link_column, count_column, tags_column) =
+        (column("link"), column("count"), column("tags"));
+    let now = list.now();
+    let mut rows = Vec::new();
+    for record in r.records() {
+        let record = record.map_err(|e| e.to_string())?;
+        let cell = |i: Option<usize>| {
+            i.and_then(|i| record.get(i))
+                .map(str::trim)
+                .filter(|c| !c.is_empty())
+        };
+        let name = match cell(Some(name_column)) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        let count = match cell(count_column) {
+            Some(c) => Some(
+                c.parse::<u64>()
+                    .map_err(|_| format!("the count of {} is not a number: {:?}", name, c))?,
+            ),
+            None => None,
+        };
+        let existing = list
+            .things
+            .iter()
+            .position(|x| x.name == name)
+            .map(|i| (false, i))
+            .or_else(|| {
+                list.archived
+                    .iter()
+                    .position(|x| x.name == name)
+                    .map(|i| (true, i))
+            });
+        if existing.is_some() && !merge {
+            return Err(format!(
+                "{} is already in the list, so import with merge to update it",
+                name
+            ));
+        }
+        let thing = match existing {
+            Some((false, i)) => &mut list.things[i],
+            Some((true, i)) => &mut list.archived[i],
+            None => {
+                let thing = list.new_thing(&name, now);
+                list.things.push(thing);
+                list.things.last_mut().expect("we just added it")
+            }
+        };
+        if let Some(link) = cell(link_column) {
+            thing.link = Some(link.to_string());
+        }
+        if let Some(count) = count {
+            thing.count = count;
+        }
+        if let Some(tags) = cell(tags_column) {
+            thing.tags = parse_tags(tags);
+        }
+        rows.push((name, existing.is_none()));
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list() -> ThingList {
+        let mut list = ThingList::with_things(&[]);
+        let mut soup = list.new_thing("soup", 0.0);
+        soup.tags = vec!["dinner".to_string()];
+        list.things.push(soup);
+        list
+    }
+
+    const CSV: &str = "Tags,Name,Link,Notes
+\"vegetarian, dinner\",soup,,hot
+,pancakes,https://example.com/pancakes,
+";
+
+    #[test]
+    fn merging_updates_things_by_name() {
+        let mut list = list();
+        let rows = apply(&mut list, CSV.as_bytes(), true).unwrap();
+        assert_eq!(
+            rows,
+            vec![("soup".to_string(), false), ("pancakes".to_string(), true)]
+        );
+        assert_eq!(list.things.len(), 2);
+        assert_eq!(list.things[0].tags, vec!["dinner", "vegetarian"]);
+        assert_eq!(
+            list.things[1].link.as_deref(),
+            Some("https://example.com/pancakes")
+        );
+    }
+
+    #[test]
+    fn exports_can_be_imported() {
+        let mut list = list();
+        apply(&mut list, CSV.as_bytes(), true).unwrap();
+        assert_eq!(list.things.len(), 2);
+        list.things[1].count = 4;
+        let mut copy = list.clone();
+        copy.things.clear();
+        apply(&mut copy, export(&list).as_bytes(), false).unwrap();
+        assert_eq!(export(&copy), export(&list));
+    }
+
+    #[test]
+    fn names_in_the_list_are_only_imported_by_merging() {
+        let mut list = list();
+        list.things[0].count = 9;
+        assert!(apply(&mut list.clone(), b"name\nsoup\n", false).is_err());
+        assert!(apply(&mut list.clone(), b"name\ntoast\ntoast\n", false).is_err());
+        list.archive("soup");
+        assert!(apply(&mut list.clone(), b"name\nsoup\n", false).is_err());
+        let rows = apply(&mut list, b"name,link\nsoup,https://example.com\n", true).unwrap();
+        assert_eq!(rows, vec![("soup".to_string(), false)]);
+        assert!(list.things.is_empty());
+        assert_eq!(list.archived[0].count, 9);
+        assert_eq!(
+            list.archived[0].link.as_deref(),
+            Some("https://example.com")
+        );
+    }
+
+    #[test]
+    fn a_name_column_is_required() {
+        assert!(apply(&mut list(), b"title\nsoup\n", true).is_err());
+        assert!(apply(&mut list(), b"name,count\nsoup,many\n", true).is_err());
+    }
+}
@@ -0,0 +1,552 @@
+//! Keep the data in a single SQLite database, rather than in a tree of
+//! files, which stays quick with hundreds of lists.
+//!
+//! The database holds the collections, the lists and their settings,
+//! each thing as a row, and each history event as a row.  Everything
+//! else in a collection, such as the changes kept for undo, is
+//! kept as whole files in the `files` table.  We still present all of
+//! this as the files described in `store`, so nothing else needs to
+//! know where the data lives.
+//!
+//! An existing `data/` tree is copied into a database by running
+//! `thing-lists migrate --sqlite lists.db`.
+
+use crate::store::{self, Store};
+use crate::{ListFile, StoredList, Thing};
+use clapme::ClapMe;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::sync::Mutex;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS collections (
+    code TEXT PRIMARY KEY
+);
+CREATE TABLE IF NOT EXISTS lists (
+    code TEXT NOT NULL,
+    name TEXT NOT NULL,
+    settings TEXT NOT NULL,
+    PRIMARY KEY (code, name)
+);
+CREATE TABLE IF NOT EXISTS things (
+    code TEXT NOT NULL,
+    list TEXT NOT NULL,
+    archived INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    thing TEXT NOT NULL,
+    PRIMARY KEY (code, list, archived, position)
+);
+CREATE TABLE IF NOT EXISTS history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    code TEXT NOT NULL,
+    list TEXT NOT NULL,
+    event TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS history_list ON history (code, list);
+CREATE TABLE IF NOT EXISTS files (
+    code TEXT NOT NULL,
+    name TEXT NOT NULL,
+    contents TEXT NOT NULL,
+    PRIMARY KEY (code, name)
+);
+";
+
+pub struct Sqlite {
+    db: Mutex<Connection>,
+}
+
+impl Sqlite {
+    pub fn open(path: &str) -> Self {
+        let db = Connection::open(path).expect("error opening database");
+        db.execute_batch(SCHEMA).expect("error creating tables");
+        Sqlite { db: Mutex::new(db) }
+    }
+    /// The database, even if a request panicked while holding it.  Every
+    /// change is made in a transaction, which is rolled back if we panic
+    /// part way, so there is nothing half done to worry about.
+    fn db(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.db.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Which table a file of a collection is kept in.
+enum Kind<'a> {
+    List,
+    History(&'a str),
+    File,
+}
+
+fn kind(name: &str) -> Kind<'_> {
+    if let Some(list) = name.strip_prefix(".history/") {
+        if !list.contains('/') {
+            return Kind::History(list);
+        }
+    }
+    // Character sheets are YAML files alongside the lists.
+    if name.starts_with('.') || name.contains('/') || name.ends_with(".yaml") {
+        Kind::File
+    } else {
+        Kind::List
+    }
+}
+
+fn json<T: serde::Serialize>(x: &T) -> String {
+    serde_json::to_string(x).expect("error writing json")
+}
+
+fn read_file(db: &Connection, code: &str, name: &str) -> rusqlite::Result<Option<String>> {
+    db.query_row(
+        "SELECT contents FROM files WHERE code = ?1 AND name = ?2",
+        params![code, name],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// The settings and things of a list, or `None` if it has none.  A row
+/// that cannot be read is reported and the list left unread, rather
+/// than panicking while we hold the database.
+fn read_list(db: &Connection, code: &str, name: &str) -> rusqlite::Result<Option<ListFile>> {
+    let settings: Option<String> = db
+        .query_row(
+            "SELECT settings FROM lists WHERE code = ?1 AND name = ?2",
+            params![code, name],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let settings = match settings.map(|s| serde_json::from_str(&s)) {
+        Some(Ok(settings)) => settings,
+        Some(Err(e)) => {
+            eprintln!("Bad settings for {}/{} in database: {}", code, name, e);
+            return Ok(None);
+        }
+        None => return Ok(None),
+    };
+    let mut stmt = db.prepare_cached(
+        "SELECT thing FROM things WHERE code = ?1 AND list = ?2 AND archived = ?3
+         ORDER BY position",
+    )?;
+    let mut things = |archived: bool| -> rusqlite::Result<Option<Vec<Thing>>> {
+        let rows: Vec<String> = stmt
+            .query_map(params![code, name, archived], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        let mut things = Vec::new();
+        for row in rows {
+            match serde_json::from_str(&row) {
+                Ok(thing) => things.push(thing),
+                Err(e) => {
+                    eprintln!("Bad thing in {}/{} in database: {}", code, name, e);
+                    return Ok(None);
+                }
+            }
+        }
+        Ok(Some(things))
+    };
+    let (things, archived) = match (things(false)?, things(true)?) {
+        (Some(things), Some(archived)) => (things, archived),
+        _ => return Ok(None),
+    };
+    Ok(Some(ListFile {
+        settings,
+        things,
+        archived,
+    }))
+}
+
+/// Save a list, touching only the rows that changed, so choosing one
+/// thing from a long list writes only a few rows.
+fn write_list(db: &Connection, code: &str, name: &str, file: &ListFile) -> rusqlite::Result<()> {
+    db.execute(
+        "INSERT OR IGNORE INTO collections (code) VALUES (?1)",
+        params![code],
+    )?;
+    db.execute(
+        "INSERT INTO lists (code, name, settings) VALUES (?1, ?2, ?3)
+         ON CONFLICT (code, name) DO UPDATE SET settings = excluded.settings
+         WHERE settings IS NOT excluded.settings",
+        params![code, name, json(&file.settings)],
+    )?;
+    // A list that could not be read as one was kept as a file.
+    db.execute(
+        "DELETE FROM files WHERE code = ?1 AND name = ?2",
+        params![code, name],
+    )?;
+    let mut upsert = db.prepare_cached(
+        "INSERT INTO things (code, list, archived, position, name, thing)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT (code, list, archived, position) DO UPDATE
+         SET name = excluded.name, thing = excluded.thing
+         WHERE thing IS NOT excluded.thing",
+    )?;
+    for (archived, things) in [(false, &file.things), (true, &file.archived)] {
+        for (position, thing) in things.iter().enumerate() {
+            upsert.execute(params![
+                code,
+                name,
+                archived,
+                position,
+                thing.name,
+                json(thing)
+            ])?;
+        }
+        db.execute(
+            "DELETE FROM things WHERE code = ?1 AND list = ?2 AND archived = ?3 AND position >= ?4",
+            params![code, name, archived, things.len()],
+        )?;
+    }
+    Ok(())
+}
+
+/// Save a file of a collection in whichever table it belongs in.
+fn write_file(db: &Connection, code: &str, name: &str, contents: &str) -> rusqlite::Result<()> {
+    db.execute(
+        "INSERT OR IGNORE INTO collections (code) VALUES (?1)",
+        params![code],
+    )?;
+    let list = match kind(name) {
+        Kind::List => serde_yaml::from_str::<StoredList>(contents).ok(),
+        _ => None,
+    };
+    match (kind(name), list) {
+        (_, Some(stored)) => write_list(db, code, name, &stored.into_file()),
+        (Kind::History(list), _) => {
+            db.execute(
+                "DELETE FROM history WHERE code = ?1 AND list = ?2",
+                params![code, list],
+            )?;
+            for event in contents.lines() {
+                db.execute(
+                    "INSERT INTO history (code, list, event) VALUES (?1, ?2, ?3)",
+                    params![code, list, event],
+                )?;
+            }
+            Ok(())
+        }
+        // Anything that does not read as a list is kept as it is.
+        _ => db
+            .execute(
+                "INSERT OR REPLACE INTO files (code, name, contents) VALUES (?1, ?2, ?3)",
+                params![code, name, contents],
+            )
+            .map(|_| ()),
+    }
+}
+
+impl Store for Sqlite {
+    fn collections(&self) -> Vec<String> {
+        let db = self.db();
+        let mut stmt = db
+            .prepare("SELECT code FROM collections ORDER BY code")
+            .expect("error reading collections");
+        let codes = stmt
+            .query_map([], |row| row.get(0))
+            .and_then(|rows| rows.collect())
+            .expect("error reading collections");
+        codes
+    }
+    fn files(&self, code: &str) -> Vec<String> {
+        let db = self.db();
+        let mut stmt = db
+            .prepare(
+                "SELECT name FROM lists WHERE code = ?1
+                 UNION SELECT '.history/' || list FROM history WHERE code = ?1
+                 UNION SELECT name FROM files WHERE code = ?1",
+            )
+            .expect("error reading files");
+        let names = stmt
+            .query_map(params![code], |row| row.get(0))
+            .and_then(|rows| rows.collect())
+            .expect("error reading files");
+        names
+    }
+    fn read(&self, code: &str, name: &str) -> Option<String> {
+        let db = self.db();
+        match kind(name) {
+            Kind::List => read_list(&db, code, name).and_then(|list| match list {
+                Some(list) => Ok(Some(
+                    serde_yaml::to_string(&list).expect("error writing yaml"),
+                )),
+                None => read_file(&db, code, name),
+            }),
+            Kind::History(list) => db
+                .prepare("SELECT event FROM history WHERE code = ?1 AND list = ?2 ORDER BY id")
+                .and_then(|mut stmt| {
+                    let events: Vec<String> = stmt
+                        .query_map(params![code, list], |row| row.get(0))?
+                        .collect::<rusqlite::Result<_>>()?;
+                    Ok(if events.is_empty() {
+                        None
+                    } else {
+                        Some(events.iter().map(|e| format!("{}\n", e)).collect())
+                    })
+                }),
+            Kind::File => read_file(&db, code, name),
+        }
+        .unwrap_or_else(|e| {
+            eprintln!("Unable to read {}/{} from database: {}", code, name, e);
+            None
+        })
+    }
+    /// Lists are read straight from their rows.
+    fn read_list(&self, code: &str, name: &str) -> Option<ListFile> {
+        let db = self.db();
+        match read_list(&db, code, name) {
+            Ok(Some(list)) => Some(list),
+            Ok(None) => read_file(&db, code, name)
+                .ok()
+                .flatten()
+                .and_then(|c| serde_yaml::from_str::<StoredList>(&c).ok())
+                .map(StoredList::into_file),
+            Err(e) => {
+                eprintln!("Unable to read {}/{} from database: {}", code, name, e);
+                None
+            }
+        }
+    }
+    fn write_list(&self, code: &str, name: &str, list: &ListFile) {
+        let mut db = self.db();
+        let tx = db.transaction().expect("error writing database");
+        write_list(&tx, code, name, list).expect("error writing database");
+        tx.commit().expect("error writing database");
+    }
+    fn write(&self, code: &str, name: &str, contents: &str) {
+        let mut db = self.db();
+        let tx = db.transaction().expect("error writing database");
+        write_file(&tx, code, name, contents).expect("error writing database");
+        tx.commit().expect("error writing database");
+    }
+    fn delete(&self, code: &str, name: &str) {
+        let mut db = self.db();
+        let tx = db.transaction().expect("error writing database");
+        match kind(name) {
+            Kind::List => tx
+                .execute(
+                    "DELETE FROM lists WHERE code = ?1 AND name = ?2",
+                    params![code, name],
+                )
+                .and_then(|_| {
+                    tx.execute(
+                        "DELETE FROM things WHERE code = ?1 AND list = ?2",
+                        params![code, name],
+                    )
+                })
+                .and_then(|_| {
+                    tx.execute(
+                        "DELETE FROM files WHERE code = ?1 AND name = ?2",
+                        params![code, name],
+                    )
+                })
+                .map(|_| ()),
+            Kind::History(list) => tx
+                .execute(
+                    "DELETE FROM history WHERE code = ?1 AND list = ?2",
+                    params![code, list],
+                )
+                .map(|_| ()),
+            Kind::File => tx
+                .execute(
+                    "DELETE FROM files WHERE code = ?1 AND name = ?2",
+                    params![code, name],
+                )
+                .map(|_| ()),
+        }
+        .expect("error writing database");
+        tx.commit().expect("error writing database");
+    }
+    /// Everything goes in one transaction, so a failure part way leaves
+    /// the collection as it was.
+    fn replace(&self, code: &str, files: &[(String, String)]) {
+        let mut db = self.db();
+        let tx = db.transaction().expect("error writing database");
+        for table in ["lists", "things", "history", "files"] {
+            tx.execute(
+                &format!("DELETE FROM {} WHERE code = ?1", table),
+                params![code],
+            )
+            .expect("error writing database");
+        }
+        for (name, contents) in files {
+            write_file(&tx, code, name, contents).expect("error writing database");
+        }
+        tx.commit().expect("error writing database");
+    }
+    /// Each event is its own row, so appending to the history never
+    /// reads or rewrites the events already there.
+    fn append(&self, code: &str, name: &str, contents: &str) {
+        let list = match kind(name) {
+            Kind::History(list) => list,
+            _ => {
+                let mut all = self.read(code, name).unwrap_or_default();
+                all.push_str(contents);
+                return self.write(code, name, &all);
+            }
+        };
+        let mut db = self.db();
+        let tx = db.transaction().expect("error writing database");
+        tx.execute(
+            "INSERT OR IGNORE INTO collections (code) VALUES (?1)",
+            params![code],
+        )
+        .expect("error writing database");
+        for event in contents.lines() {
+            tx.execute(
+                "INSERT INTO history (code, list, event) VALUES (?1, ?2, ?3)",
+                params![code, list, event],
+            )
+            .expect("error writing database");
+        }
+        tx.commit().expect("error writing database");
+    }
+    fn lists(&self, code: &str) -> Vec<String> {
+        let db = self.db();
+        let mut stmt = db
+            .prepare(
+                "SELECT name FROM lists WHERE code = ?1
+                 UNION SELECT name FROM files WHERE code = ?1
+                   AND name NOT LIKE '.%' AND name NOT LIKE '%/%'",
+            )
+            .expect("error reading lists");
+        let names = stmt
+            .query_map(params![code], |row| row.get(0))
+            .and_then(|rows| rows.collect())
+            .expect("error reading lists");
+        names
+    }
+}
+
+#[derive(Debug, ClapMe)]
+pub struct Flags {
+    /// The directory to copy the data from (default data).
+    data: Option<String>,
+    /// The database to copy the data into.
+    sqlite: String,
+}
+
+pub fn run(flags: Flags) {
+    let data = flags.data.unwrap_or_else(|| "data".to_string());
+    let from = store::Files::new(&data);
+    let to = Sqlite::open(&flags.sqlite);
+    for code in from.collections() {
+        let files = migrate(&from, &to, &code);
+        println!("{}: copied {} files", code, files);
+    }
+}
+
+/// Copy every file in the collection `code`, returning how many there
+/// were.
+fn migrate(from: &dyn Store, to: &dyn Store, code: &str) -> usize {
+    let files = from.files(code);
+    for name in files.iter() {
+        if let Some(contents) = from.read(code, name) {
+            to.write(code, name, &contents);
+        }
+    }
+    files.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{history, ThingList};
+
+    #[test]
+    fn lists_are_kept_as_rows() {
+        let db = Sqlite::open(":memory:");
+        let mut list = ThingList::read(&db, "code", "dinner");
+        for name in ["soup", "salad", "toast"] {
+            let thing = list.new_thing(name, 0.0);
+            list.things.push(thing);
+        }
+        list.archive("toast");
+        list.save(&db);
+        list.record(&db, "soup", history::Action::New);
+        list.record(&db, "salad", history::Action::New);
+
+        let read = ThingList::read(&db, "code", "dinner");
+        assert_eq!(read, list);
+        assert_eq!(read.archived.len(), 1);
+        assert_eq!(history::read(&db, "code", "dinner").len(), 2);
+        assert_eq!(db.lists("code"), vec!["dinner"]);
+        assert_eq!(db.collections(), vec!["code"]);
+        let things: i64 = db
+            .db
+            .lock()
+            .unwrap()
+            .query_row("SELECT count(*) FROM things", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(things, 3);
+
+        // Saving the same list again leaves nothing to undo.
+        read.save(&db);
+        assert_eq!(crate::undo::len(&db, "code", "dinner"), 0);
+        db.delete("code", "dinner");
+        assert!(db.lists("code").is_empty());
+    }
+
+    #[test]
+    fn migrating_copies_every_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let files = store::Files::new(dir.path());
+        let mut list = ThingList::read(&files, "code", "dinner");
+        let thing = list.new_thing("soup", 0.0);
+        list.things.push(thing);
+        list.save(&files);
+        list.record(&files, "soup", history::Action::New);
+        list.archive("soup");
+        list.save(&files);
+        files.write("code", "sheet.yaml", "sections: []\n");
+
+        let db = Sqlite::open(":memory:");
+        assert_eq!(migrate(&files, &db, "code"), files.files("code").len());
+        let mut before = files.files("code");
+        let mut after = db.files("code");
+        before.sort();
+        after.sort();
+        assert_eq!(before, after);
+        for name in before {
+            assert_eq!(
+                files.read("code", &name),
+                db.read("code", &name),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn replacing_a_collection_removes_the_rest() {
+        let db = Sqlite::open(":memory:");
+        db.write("code", "dinner", "things: []\n");
+        db.append("code", ".history/dinner", "{}\n");
+        db.write("code", ".undo/dinner/top", "{}");
+        db.write("other", "lunch", "things: []\n");
+        db.replace(
+            "code",
+            &[
+                ("lunch".to_string(), "things: []\n".to_string()),
+                (".history/lunch".to_string(), "{}\n".to_string()),
+            ],
+        );
+        let mut files = db.files("code");
+        files.sort();
+        assert_eq!(files, vec![".history/lunch", "lunch"]);
+        assert_eq!(db.lists("other"), vec!["lunch"]);
+    }
+
+    #[test]
+    fn bad_rows_are_not_read() {
+        let db = Sqlite::open(":memory:");
+        let mut list = ThingList::read(&db, "code", "dinner");
+        let thing = list.new_thing("soup", 0.0);
+        list.things.push(thing);
+        list.save(&db);
+        db.db()
+            .execute("UPDATE things SET thing = '{'", [])
+            .unwrap();
+        assert!(db.read_list("code", "dinner").is_none());
+        assert_eq!(db.read("code", "dinner"), None);
+        // The database is still there for the next request.
+        list.save(&db);
+        assert_eq!(db.read_list("code", "dinner").unwrap().things.len(), 1);
+    }
+}
@@ -0,0 +1,369 @@
+//! Where the data lives.
+//!
+//! Each collection is a set of text files, named by their path within
+//! the collection: a list is saved as `<list>`, its history as
+//! `.history/<list>` and the changes that can be undone in
+//! `.undo/<list>/`.  A `Store` keeps those files somewhere, and
+//! everything else reads and writes them only through a `Store`.
+
+use crate::atomicfile;
+use crate::{ListFile, StoredList};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub trait Store: Send + Sync {
+    /// The codes of every collection.
+    fn collections(&self) -> Vec<String>;
+    /// Every file in a collection, such as `dinner` or `.history/dinner`.
+    fn files(&self, code: &str) -> Vec<String>;
+    fn read(&self, code: &str, name: &str) -> Option<String>;
+    /// Replace a file, all at once.
+    fn write(&self, code: &str, name: &str, contents: &str);
+    fn delete(&self, code: &str, name: &str);
+    /// Replace everything in a collection with `files`, all at once, so
+    /// that nobody ever sees some files replaced and others not.
+    fn replace(&self, code: &str, files: &[(String, String)]);
+    /// Add to the end of a file, creating it if need be.
+    fn append(&self, code: &str, name: &str, contents: &str) {
+        let mut all = self.read(code, name).unwrap_or_default();
+        all.push_str(contents);
+        self.write(code, name, &all);
+    }
+    /// A list, or `None` if there is none that can be read.
+    fn read_list(&self, code: &str, name: &str) -> Option<ListFile> {
+        let contents = self.read(code, name)?;
+        serde_yaml::from_str::<StoredList>(&contents)
+            .ok()
+            .map(StoredList::into_file)
+    }
+    fn write_list(&self, code: &str, name: &str, list: &ListFile) {
+        let contents = serde_yaml::to_string(list).expect("error writing yaml");
+        self.write(code, name, &contents);
+    }
+    /// The names of the lists in a collection, which are the files that
+    /// are neither hidden nor in a directory.
+    fn lists(&self, code: &str) -> Vec<String> {
+        self.files(code)
+            .into_iter()
+            .filter(|name| !name.starts_with('.') && !name.contains('/'))
+            .collect()
+    }
+}
+
+/// The SQLite database at `sqlite` if there is one, and otherwise the
+/// files in `data/`.
+pub fn open(sqlite: Option<&str>) -> Arc<dyn Store> {
+    match sqlite {
+        Some(path) => Arc::new(crate::sqlite::Sqlite::open(path)),
+        None => Arc::new(Files::new("data")),
+    }
+}
+
+/// Add every file in the collection `code` to a tar archive, under
+/// `dir`, which is how backups and snapshots are laid out.
+pub fn archive<W: Write>(
+    store: &dyn Store,
+    code: &str,
+    dir: &str,
+    ar: &mut tar::Builder<W>,
+) -> std::io::Result<()> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    for name in store.files(code) {
+        if let Some(contents) = store.read(code, &name) {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(now);
+            header.set_cksum();
+            ar.append_data(
+                &mut header,
+                format!("{}/{}", dir, name),
+                contents.as_bytes(),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Make sure that `name` is a path within a collection, which cannot
+/// reach outside it however it is joined to the collection's path.
+pub fn check_name(name: &str) -> Result<(), String> {
+    if name
+        .split('/')
+        .any(|p| p.is_empty() || p == "." || p == ".." || p.contains(['\\', '\0']))
+    {
+        return Err(format!("{:?} is not a good name", name));
+    }
+    Ok(())
+}
+
+/// The files are just files, in `<root>/<code>/`.
+pub struct Files {
+    root: PathBuf,
+}
+
+impl Files {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Files { root: root.into() }
+    }
+    /// The directory of a collection.  Codes and names come from URLs
+    /// and forms, so we check them before they get near the filesystem.
+    fn collection(&self, code: &str) -> Result<PathBuf, String> {
+        check_name(code)?;
+        if code.contains('/') {
+            return Err(format!("{:?} is not a good code", code));
+        }
+        Ok(self.root.join(code))
+    }
+    fn path(&self, code: &str, name: &str) -> Result<PathBuf, String> {
+        check_name(name)?;
+        Ok(self.collection(code)?.join(name))
+    }
+}
+
+/// The names of the entries in a directory that are not hidden, and
+/// whether each is itself a directory.
+fn entries(dir: &std::path::Path) -> Vec<(String, bool)> {
+    std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| {
+            (
+                entry.file_name().to_string_lossy().to_string(),
+                entry.path().is_dir(),
+            )
+        })
+        .collect()
+}
+
+impl Store for Files {
+    fn collections(&self) -> Vec<String> {
+        entries(&self.root)
+            .into_iter()
+            .filter(|(name, is_dir)| *is_dir && !name.starts_with('.'))
+            .map(|(name, _)| name)
+            .collect()
+    }
+    fn files(&self, code: &str) -> Vec<String> {
+        let top = match self.collection(code) {
+            Ok(top) => top,
+            Err(_) => return Vec::new(),
+        };
+        let mut names = Vec::new();
+        let mut dirs = vec![String::new()];
+        while let Some(dir) = dirs.pop() {
+            for (name, is_dir) in entries(&top.join(&dir)) {
+                // Half-written files from `AtomicFile` are not there yet.
+                if name.starts_with(".tmp") {
+                    continue;
+                }
+                let name = format!("{}{}", dir, name);
+                if is_dir {
+                    dirs.push(format!("{}/", name));
+                } else {
+                    names.push(name);
+                }
+            }
+        }
+        names
+    }
+    fn read(&self, code: &str, name: &str) -> Option<String> {
+        std::fs::read_to_string(self.path(code, name).ok()?).ok()
+    }
+    fn write(&self, code: &str, name: &str, contents: &str) {
+        let p = self.path(code, name).expect("error naming file");
+        let mut f = atomicfile::AtomicFile::create(p).expect("error creating file");
+        f.write_all(contents.as_bytes())
+            .expect("error writing file");
+    }
+    fn delete(&self, code: &str, name: &str) {
+        if let Ok(p) = self.path(code, name) {
+            std::fs::remove_file(p).ok();
+        }
+    }
+    /// The new collection is written beside the old one and renamed
+    /// into place, and only then is the old one removed.
+    fn replace(&self, code: &str, files: &[(String, String)]) {
+        let top = self.collection(code).expect("error naming collection");
+        std::fs::create_dir_all(&self.root).expect("error creating data directory");
+        let staging = tempfile::TempDir::new_in(&self.root).expect("error creating directory");
+        for (name, contents) in files {
+            check_name(name).expect("error naming file");
+            let p = staging.path().join(name);
+            if let Some(dir) = p.parent() {
+                std::fs::create_dir_all(dir).expect("error creating directory");
+            }
+            let mut f = std::fs::File::create(&p).expect("error creating file");
+            f.write_all(contents.as_bytes())
+                .expect("error writing file");
+            f.sync_data().expect("error writing file");
+        }
+        // The old collection goes here, to be removed when this drops.
+        let old = tempfile::TempDir::new_in(&self.root).expect("error creating directory");
+        if top.exists() {
+            std::fs::rename(&top, old.path().join(code)).expect("error moving collection");
+        }
+        if let Err(e) = std::fs::rename(staging.path(), &top) {
+            std::fs::rename(old.path().join(code), &top).ok();
+            panic!("error replacing collection: {}", e);
+        }
+        // It has been renamed away, so there is nothing left to remove.
+        let _ = staging.keep();
+    }
+    /// Appending never rewrites the file, so this is cheap however long
+    /// the file gets.
+    fn append(&self, code: &str, name: &str, contents: &str) {
+        let p = self.path(code, name).expect("error naming file");
+        if let Some(dir) = p.parent() {
+            std::fs::create_dir_all(dir).ok();
+        }
+        let mut f = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&p)
+            .expect("error opening file");
+        f.write_all(contents.as_bytes())
+            .expect("error appending to file");
+    }
+    /// Only the top directory of the collection need be read.
+    fn lists(&self, code: &str) -> Vec<String> {
+        let top = match self.collection(code) {
+            Ok(top) => top,
+            Err(_) => return Vec::new(),
+        };
+        entries(&top)
+            .into_iter()
+            .filter(|(name, is_dir)| !is_dir && !name.starts_with('.'))
+            .map(|(name, _)| name)
+            .collect()
+    }
+}
+
+/// Everything is kept in memory, and lost when the store is dropped.
+#[cfg(test)]
+#[derive(Default)]
+pub struct Memory {
+    files: std::sync::Mutex<std::collections::BTreeMap<(String, String), String>>,
+}
+
+#[cfg(test)]
+impl Store for Memory {
+    fn collections(&self) -> Vec<String> {
+        let mut codes: Vec<String> = self
+            .files
+            .lock()
+            .unwrap()
+            .keys()
+            .map(|(code, _)| code.clone())
+            .collect();
+        codes.dedup();
+        codes
+    }
+    fn files(&self, code: &str) -> Vec<String> {
+        self.files
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|(c, _)| c == code)
+            .map(|(_, name)| name.clone())
+            .collect()
+    }
+    fn read(&self, code: &str, name: &str) -> Option<String> {
+        let key = (code.to_string(), name.to_string());
+        self.files.lock().unwrap().get(&key).cloned()
+    }
+    fn write(&self, code: &str, name: &str, contents: &str) {
+        let key = (code.to_string(), name.to_string());
+        self.files.lock().unwrap().insert(key, contents.to_string());
+    }
+    fn delete(&self, code: &str, name: &str) {
+        let key = (code.to_string(), name.to_string());
+        self.files.lock().unwrap().remove(&key);
+    }
+    fn replace(&self, code: &str, files: &[(String, String)]) {
+        let mut all = self.files.lock().unwrap();
+        all.retain(|(c, _), _| c != code);
+        for (name, contents) in files {
+            all.insert((code.to_string(), name.clone()), contents.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(store: &dyn Store) {
+        store.write("code", "dinner", "soup");
+        store.append("code", ".history/dinner", "one\n");
+        store.append("code", ".history/dinner", "two\n");
+        store.write("other", "lunch", "salad");
+        assert_eq!(store.read("code", "dinner").as_deref(), Some("soup"));
+        assert_eq!(
+            store.read("code", ".history/dinner").as_deref(),
+            Some("one\ntwo\n")
+        );
+        assert_eq!(store.lists("code"), vec!["dinner"]);
+        let mut files = store.files("code");
+        files.sort();
+        assert_eq!(files, vec![".history/dinner", "dinner"]);
+        let mut codes = store.collections();
+        codes.sort();
+        assert_eq!(codes, vec!["code", "other"]);
+        store.delete("code", "dinner");
+        assert_eq!(store.read("code", "dinner"), None);
+        assert!(store.lists("code").is_empty());
+
+        store.replace(
+            "code",
+            &[
+                ("lunch".to_string(), "soup".to_string()),
+                (".history/lunch".to_string(), "one\n".to_string()),
+            ],
+        );
+        let mut files = store.files("code");
+        files.sort();
+        assert_eq!(files, vec![".history/lunch", "lunch"]);
+        assert_eq!(store.read("code", "lunch").as_deref(), Some("soup"));
+        assert_eq!(store.read("other", "lunch").as_deref(), Some("salad"));
+    }
+
+    #[test]
+    fn memory_store() {
+        check(&Memory::default());
+    }
+
+    #[test]
+    fn file_store() {
+        let dir = tempfile::TempDir::new().unwrap();
+        check(&Files::new(dir.path()));
+    }
+
+    #[test]
+    fn files_stay_in_their_collection() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let outside = dir.path().join("outside");
+        std::fs::write(&outside, "secret").unwrap();
+        let files = Files::new(dir.path().join("data"));
+        let outside = outside.to_str().unwrap();
+        for (code, name) in [
+            ("code", outside),
+            (outside, "list"),
+            ("code", "../../outside"),
+            ("..", "outside"),
+            ("code", ".undo//0"),
+            ("", "list"),
+        ] {
+            assert!(files.path(code, name).is_err(), "{} {}", code, name);
+            assert_eq!(files.read(code, name), None);
+        }
+        assert!(files.lists("..").is_empty());
+        assert!(files.files("..").is_empty());
+        assert!(files.path("code", ".undo/list/0").is_ok());
+    }
+}
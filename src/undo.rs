@@ -0,0 +1,304 @@
+//! A stack of the changes to each list, so that they can be undone.
+//!
+//! Whenever a list is saved, what the change did to it is pushed onto
+//! its stack.  Rather than the whole of the list before the change, we
+//! keep only the order of its things and the earlier version of each
+//! thing that changed, so choosing one thing from a long list costs
+//! little more than the one thing.  The changes to a list form a ring
+//! of `MAX_UNDO` files in `.undo/<list>/` in its collection, with `top`
+//! in the same directory saying where the ring is, so that each push
+//! only writes the one change being pushed.
+
+use crate::store::Store;
+use crate::{ListFile, ListSettings, Thing};
+use serde::{Deserialize, Serialize};
+
+/// How many changes to a list we hold on to.
+const MAX_UNDO: usize = 50;
+
+/// Where a list is in its ring of changes.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Top {
+    /// How many changes have ever been pushed, so the most recent is
+    /// in slot `(pushed - 1) % MAX_UNDO`.
+    pushed: usize,
+    /// How many of the most recent changes are there to undo.
+    len: usize,
+}
+
+/// What a list was before one change, holding only what the change
+/// touched.  The things and the archived things are kept apart, since
+/// an archived thing may share its name with one that is not.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Change {
+    /// The settings, if the change altered them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    settings: Option<ListSettings>,
+    /// The names of the things, in order.
+    things: Vec<String>,
+    archived: Vec<String>,
+    /// The earlier version of each thing that the change altered or
+    /// removed.
+    changed: Vec<Thing>,
+    /// Likewise for the archived things.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    changed_archived: Vec<Thing>,
+}
+
+fn json(thing: &Thing) -> String {
+    serde_json::to_string(thing).expect("error writing json")
+}
+
+/// The things in `before` that are not just the same in `after`.  A
+/// name that comes up more than once is matched in order.
+fn changed(before: &[Thing], after: &[Thing]) -> Vec<Thing> {
+    let mut after: Vec<&Thing> = after.iter().collect();
+    before
+        .iter()
+        .filter(|x| match after.iter().position(|y| y.name == x.name) {
+            Some(i) => json(after.remove(i)) != json(x),
+            None => true,
+        })
+        .cloned()
+        .collect()
+}
+
+/// The things with these names, as they were if they changed and
+/// otherwise as they are now.
+fn restore(names: &[String], mut changed: Vec<Thing>, mut now: Vec<Thing>) -> Vec<Thing> {
+    names
+        .iter()
+        .filter_map(|name| take(&mut changed, name).or_else(|| take(&mut now, name)))
+        .collect()
+}
+
+/// Take the first thing with this name out of `things`.
+fn take(things: &mut Vec<Thing>, name: &str) -> Option<Thing> {
+    let i = things.iter().position(|x| x.name == name)?;
+    Some(things.remove(i))
+}
+
+impl Change {
+    /// What it takes to get back from `after` to `before`, or `None` if
+    /// they are just the same.
+    pub fn between(before: &ListFile, after: &ListFile) -> Option<Change> {
+        let names = |things: &[Thing]| things.iter().map(|x| x.name.clone()).collect();
+        let change = Change {
+            settings: Some(before.settings.clone()).filter(|s| *s != after.settings),
+            things: names(&before.things),
+            archived: names(&before.archived),
+            changed: changed(&before.things, &after.things),
+            changed_archived: changed(&before.archived, &after.archived),
+        };
+        if change.settings.is_none()
+            && change.changed.is_empty()
+            && change.changed_archived.is_empty()
+            && change.things == names(&after.things)
+            && change.archived == names(&after.archived)
+        {
+            return None;
+        }
+        Some(change)
+    }
+    /// Turn the list this change made back into the one before it.
+    pub fn revert(self, list: &mut ListFile) {
+        if let Some(settings) = self.settings {
+            list.settings = settings;
+        }
+        let things = std::mem::take(&mut list.things);
+        list.things = restore(&self.things, self.changed, things);
+        let archived = std::mem::take(&mut list.archived);
+        list.archived = restore(&self.archived, self.changed_archived, archived);
+    }
+}
+
+fn path(list: &str, name: &str) -> String {
+    format!(".undo/{}/{}", list, name)
+}
+
+fn slot(pushed: usize) -> String {
+    (pushed % MAX_UNDO).to_string()
+}
+
+fn read_top(store: &dyn Store, code: &str, list: &str) -> Top {
+    store
+        .read(code, &path(list, "top"))
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
+
+fn write_top(store: &dyn Store, code: &str, list: &str, top: &Top) {
+    let contents = serde_json::to_string(top).expect("error serializing undo stack");
+    store.write(code, &path(list, "top"), &contents);
+}
+
+/// Remember what a change did to a list.
+pub fn push(store: &dyn Store, code: &str, list: &str, change: &Change) {
+    let mut top = read_top(store, code, list);
+    let contents = serde_json::to_string(change).expect("error serializing undo stack");
+    store.write(code, &path(list, &slot(top.pushed)), &contents);
+    top.pushed += 1;
+    top.len = (top.len + 1).min(MAX_UNDO);
+    write_top(store, code, list, &top);
+}
+
+/// Forget the last `steps` changes, returning them with the most recent
+/// first, to be reverted in that order.  If there are fewer changes
+/// than that, we go back as far as we can.
+pub fn pop(store: &dyn Store, code: &str, list: &str, steps: usize) -> Vec<Change> {
+    let mut top = read_top(store, code, list);
+    let steps = steps.min(top.len);
+    let wanted = top.pushed - steps;
+    let changes: Vec<Option<Change>> = (wanted..top.pushed)
+        .rev()
+        .map(|pushed| {
+            let name = path(list, &slot(pushed));
+            let change = store
+                .read(code, &name)
+                .and_then(|s| serde_json::from_str(&s).ok());
+            store.delete(code, &name);
+            change
+        })
+        .collect();
+    if steps > 0 {
+        top.pushed = wanted;
+        top.len -= steps;
+        write_top(store, code, list, &top);
+    }
+    // Past a change we cannot read, we cannot go back any further.
+    changes.into_iter().map_while(|change| change).collect()
+}
+
+/// How many changes can be undone.
+pub fn len(store: &dyn Store, code: &str, list: &str) -> usize {
+    read_top(store, code, list).len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::Memory;
+    use crate::ThingList;
+    use rand::SeedableRng;
+
+    /// Save a list, and then save it again after choosing soup and
+    /// archiving pie, returning the list as it first was.
+    fn saved(store: &dyn Store) -> ThingList {
+        let mut list = ThingList::with_things(&["soup", "salad", "toast", "pie"]);
+        list.start_clock();
+        list.save(store);
+        let before = list.clone();
+        list.choose("soup", &mut rand::rngs::StdRng::seed_from_u64(0));
+        list.archive("pie");
+        list.save(store);
+        before
+    }
+
+    #[test]
+    fn changes_hold_only_what_changed() {
+        let store = Memory::default();
+        let before = saved(&store);
+        let change = store.read("code", ".undo/list/0").unwrap();
+        let change: Change = serde_json::from_str(&change).unwrap();
+        let changed: Vec<&str> = change.changed.iter().map(|x| x.name.as_str()).collect();
+        // The other two things are left alone.
+        assert_eq!(changed, vec!["soup", "pie"]);
+        assert!(change.changed_archived.is_empty());
+        assert_eq!(change.archived, Vec::<String>::new());
+        assert_eq!(change.settings, None);
+
+        let undone = ThingList::undo(&store, "code", "list", 1);
+        assert_eq!(json_of(&undone), json_of(&before));
+        assert_eq!(len(&store, "code", "list"), 0);
+    }
+
+    #[test]
+    fn archived_things_are_kept_apart_from_things_of_the_same_name() {
+        let store = Memory::default();
+        let mut list = ThingList::with_things(&["x", "y"]);
+        list.start_clock();
+        let rng = &mut rand::rngs::StdRng::seed_from_u64(0);
+        for _ in 0..3 {
+            list.choose("x", rng);
+        }
+        list.archive("x");
+        let thing = list.new_thing("x", 0.0);
+        list.things.push(thing);
+        list.save(&store);
+        let before = list.clone();
+        list.choose("x", rng);
+        list.save(&store);
+
+        let undone = ThingList::undo(&store, "code", "list", 1);
+        assert_eq!(undone.archived[0].count, 3);
+        assert_eq!(json_of(&undone), json_of(&before));
+    }
+
+    /// A list whose things are named by when they were added, having
+    /// been saved once as each was added.
+    fn growing(store: &dyn Store, saves: usize) {
+        let mut list = ThingList::with_things(&[]);
+        list.start_clock();
+        for i in 0..saves {
+            let thing = list.new_thing(&i.to_string(), 0.0);
+            list.things.push(thing);
+            list.save(store);
+        }
+    }
+
+    fn names(store: &dyn Store) -> Vec<String> {
+        let list = ThingList::read(store, "code", "list");
+        list.things.into_iter().map(|x| x.name).collect()
+    }
+
+    #[test]
+    fn several_changes_are_undone_at_once() {
+        let store = Memory::default();
+        growing(&store, 5);
+        assert_eq!(len(&store, "code", "list"), 4);
+        ThingList::undo(&store, "code", "list", 3);
+        assert_eq!(names(&store), vec!["0", "1"]);
+        assert_eq!(len(&store, "code", "list"), 1);
+        ThingList::undo(&store, "code", "list", 1);
+        assert_eq!(names(&store), vec!["0"]);
+        assert_eq!(len(&store, "code", "list"), 0);
+    }
+
+    #[test]
+    fn undoing_too_much_goes_back_as_far_as_it_can() {
+        let store = Memory::default();
+        growing(&store, 3);
+        assert_eq!(pop(&store, "code", "list", 0).len(), 0);
+        ThingList::undo(&store, "code", "list", 10);
+        assert_eq!(names(&store), vec!["0"]);
+        assert_eq!(len(&store, "code", "list"), 0);
+        assert!(pop(&store, "code", "list", 1).is_empty());
+        ThingList::undo(&store, "code", "list", 1);
+        assert_eq!(names(&store), vec!["0"]);
+    }
+
+    #[test]
+    fn the_oldest_changes_are_forgotten() {
+        let store = Memory::default();
+        growing(&store, MAX_UNDO + 11);
+        assert_eq!(len(&store, "code", "list"), MAX_UNDO);
+        let slots = store
+            .files("code")
+            .into_iter()
+            .filter(|name| name.starts_with(".undo/"))
+            .count();
+        assert_eq!(slots, MAX_UNDO + 1);
+        ThingList::undo(&store, "code", "list", MAX_UNDO + 5);
+        assert_eq!(names(&store).len(), 11);
+        assert_eq!(len(&store, "code", "list"), 0);
+        // The ring carries on from where it was.
+        growing(&store, 2);
+        assert_eq!(len(&store, "code", "list"), 2);
+        ThingList::undo(&store, "code", "list", 2);
+        assert_eq!(names(&store).len(), 11);
+    }
+
+    fn json_of(list: &ThingList) -> String {
+        serde_json::to_string(&(&list.settings, &list.things, &list.archived)).unwrap()
+    }
+}